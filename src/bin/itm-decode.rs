@@ -1,8 +1,10 @@
 use anyhow::{Context, Result};
-use itm_decode::{Decoder, DecoderOptions, TracePacket};
+use itm_decode::{
+    DecoderOptions, ItmReader, MalformedKind, PacketKind, RecoveryPolicy, TracePacket,
+};
 use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read};
+use std::io::{self, Read};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -18,6 +20,15 @@ struct Opt {
     )]
     naive: bool,
 
+    #[structopt(
+        short,
+        long,
+        possible_values = &["naive", "sync", "heuristic"],
+        parse(try_from_str = parse_recovery),
+        help = "Recover from decode errors by assuming the next byte is a new header (naive), by skipping to the next synchronization packet (sync), or by searching for the most likely next header (heuristic). Implies decoding continues after an error."
+    )]
+    recovery: Option<RecoveryPolicy>,
+
     #[structopt(
         short = "-s",
         long = "--stimulus-strings",
@@ -25,6 +36,15 @@ struct Opt {
     )]
     instr_as_string: bool,
 
+    #[structopt(short, long, help = "Print all fields of decoded packets and errors")]
+    verbose: bool,
+
+    #[structopt(
+        long,
+        help = "Print the number of decoded packets of each kind and of malformed packets of each variant after decoding"
+    )]
+    stats: bool,
+
     #[structopt(
         name = "FILE",
         parse(from_os_str),
@@ -33,38 +53,42 @@ struct Opt {
     file: Option<PathBuf>,
 }
 
+fn parse_recovery(s: &str) -> Result<RecoveryPolicy> {
+    match s {
+        "naive" => Ok(RecoveryPolicy::Naive),
+        "sync" => Ok(RecoveryPolicy::SkipToSync),
+        "heuristic" => Ok(RecoveryPolicy::Heuristic),
+        _ => anyhow::bail!("unknown recovery policy: {}", s),
+    }
+}
+
 fn main() -> Result<()> {
     let opt = Opt::from_args();
 
     // Open the given file, or stdin
-    let mut file: Box<dyn BufRead> = match opt.file {
-        Some(ref file) if file.to_str() != Some("-") => Box::new(BufReader::new(
+    let file: Box<dyn Read> = match opt.file {
+        Some(ref file) if file.to_str() != Some("-") => Box::new(
             File::open(file.clone()).with_context(|| format!("Failed to open {:?}", file))?,
-        )),
-        _ => Box::new(BufReader::new(io::stdin())),
+        ),
+        _ => Box::new(io::stdin()),
     };
 
-    let mut decoder = Decoder::new(DecoderOptions::default());
     let mut stim = if opt.instr_as_string {
         Some(BTreeMap::new())
     } else {
         None
     };
 
-    loop {
-        match decoder.pull() {
-            Ok(None) => {
-                let mut buf = [0_u8; 8];
-                if file
-                    .read(&mut buf)
-                    .with_context(|| "Unable to read input".to_string())?
-                    == 0
-                {
-                    break; // EOF
-                }
-                decoder.push(&buf);
-            }
-            Ok(Some(TracePacket::Instrumentation { port, payload })) if opt.instr_as_string => {
+    let mut reader = ItmReader::new(
+        file,
+        DecoderOptions {
+            recovery: opt.recovery.unwrap_or_default(),
+            ..DecoderOptions::default()
+        },
+    );
+    for packet in &mut reader {
+        match packet.with_context(|| "Unable to read input".to_string())? {
+            Ok(TracePacket::Instrumentation { port, payload }) if opt.instr_as_string => {
                 let stim = stim.as_mut().unwrap();
                 // lossily convert payload to UTF-8 string
                 stim.entry(port).or_insert_with(String::new);
@@ -83,16 +107,44 @@ fn main() -> Result<()> {
                     }
                 }
             }
-            Ok(Some(packet)) => println!("{:?}", packet),
+            Ok(packet) if opt.verbose => println!("{:#}", packet),
+            Ok(packet) => println!("{}", packet),
+
+            Err(e) => {
+                if opt.verbose {
+                    println!("Error: {:#}", e);
+                } else {
+                    println!("Error: {}", e);
+                }
+                if !opt.naive && opt.recovery.is_none() {
+                    break;
+                }
+            }
+        }
+    }
 
-            Err(e) if !opt.naive => {
-                println!("Error: {:?}", e);
-                break;
+    let discarded = reader.decoder().bytes_discarded();
+    if discarded > 0 {
+        println!("Discarded {} bytes while recovering from errors", discarded);
+    }
+
+    if opt.stats {
+        let stats = reader.decoder().stats();
+        println!(
+            "Decoded {} packets and {} malformed packets from {} bytes",
+            stats.total_packets(),
+            stats.total_malformed(),
+            stats.bytes_consumed
+        );
+        for kind in PacketKind::ALL.iter() {
+            if stats.packets(*kind) > 0 {
+                println!("  {:?}: {}", kind, stats.packets(*kind));
             }
-            Err(e) if opt.naive => {
-                println!("Error: {:?}", e);
+        }
+        for kind in MalformedKind::ALL.iter() {
+            if stats.malformed(*kind) > 0 {
+                println!("  {:?}: {}", kind, stats.malformed(*kind));
             }
-            _ => unreachable!(),
         }
     }
 
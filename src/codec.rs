@@ -0,0 +1,121 @@
+//! A [tokio_util::codec::Decoder] wrapping the sans-I/O [Decoder], for
+//! use with e.g. `tokio_util::codec::FramedRead`.
+
+use crate::{
+    BufferFull, Decoder, DecoderOptions, MalformedPacket, TimestampedTracePackets, TracePacket,
+};
+use bytes::{Buf, BytesMut};
+use std::io;
+
+/// Moves as much of `src` into `decoder` as it accepts. Data that does
+/// not fit in the decoder's input buffer is left in `src`.
+fn push(decoder: &mut Decoder, src: &mut BytesMut) {
+    if let Ok(len) = decoder.push(src) {
+        src.advance(len);
+    }
+}
+
+/// As [push], but also signals the end of the input via
+/// [Decoder::finish] once all of `src` is buffered.
+fn finish(decoder: &mut Decoder, src: &mut BytesMut) {
+    push(decoder, src);
+    if src.is_empty() {
+        decoder.finish();
+    }
+}
+
+/// Fails with [BufferFull] if nothing could be decoded although
+/// `decoder` cannot buffer the data left in `src`.
+fn check_stalled<T>(decoder: &Decoder, src: &BytesMut, item: Option<T>) -> io::Result<Option<T>> {
+    if item.is_none() && !src.is_empty() && decoder.remaining_capacity() == Some(0) {
+        return Err(io::Error::other(BufferFull));
+    }
+
+    Ok(item)
+}
+
+/// Codec that yields individual [TracePacket]s. Malformed packets are
+/// yielded as items; the codec only fails on I/O errors.
+pub struct ItmCodec {
+    decoder: Decoder,
+}
+
+impl ItmCodec {
+    pub fn new(options: DecoderOptions) -> Self {
+        Self {
+            decoder: Decoder::new(options),
+        }
+    }
+
+    /// Returns a reference to the underlying decoder.
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    /// Returns a mutable reference to the underlying decoder.
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        &mut self.decoder
+    }
+}
+
+impl tokio_util::codec::Decoder for ItmCodec {
+    type Item = Result<TracePacket, MalformedPacket>;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // The decoder buffers partial packets itself
+        push(&mut self.decoder, src);
+        let item = self.decoder.pull().transpose();
+        check_stalled(&self.decoder, src, item)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        finish(&mut self.decoder, src);
+        self.decode(src)
+    }
+}
+
+/// Codec that yields [TimestampedTracePackets] via
+/// [Decoder::pull_with_timestamp]. At the end of the input, the decoder
+/// is [finished](Decoder::finish) and packets not yet followed by a
+/// local timestamp are yielded via [Decoder::flush_timestamped].
+pub struct TimestampedItmCodec {
+    decoder: Decoder,
+}
+
+impl TimestampedItmCodec {
+    pub fn new(options: DecoderOptions) -> Self {
+        Self {
+            decoder: Decoder::new(options),
+        }
+    }
+
+    /// Returns a reference to the underlying decoder.
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    /// Returns a mutable reference to the underlying decoder.
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        &mut self.decoder
+    }
+}
+
+impl tokio_util::codec::Decoder for TimestampedItmCodec {
+    type Item = TimestampedTracePackets;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        push(&mut self.decoder, src);
+        let item = self.decoder.pull_with_timestamp();
+        check_stalled(&self.decoder, src, item)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        finish(&mut self.decoder, src);
+        match self.decode(src)? {
+            Some(set) => Ok(Some(set)),
+            None => Ok(self.decoder.flush_timestamped()),
+        }
+    }
+}
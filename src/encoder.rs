@@ -0,0 +1,397 @@
+//! An encoder for the ITM and DWT packet protocol; the inverse of
+//! [Decoder](crate::Decoder). Any references in this module refers to
+//! the same document as the decoder.
+
+use alloc::{vec, vec::Vec};
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+use crate::{
+    ExceptionAction, MemoryAccessType, TimestampDataRelation, TracePacket, SYNC_MIN_ZEROS,
+};
+
+/// A [TracePacket] could not be encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnencodablePacket {
+    /// The configured number of zeroes in a Synchronization packet is
+    /// less than 47, or does not leave the bitstream byte-aligned.
+    InvalidSyncLength(usize),
+
+    /// A timestamp does not fit in the packet it is to be encoded in.
+    TimestampOverflow {
+        /// The timestamp value.
+        ts: u64,
+
+        /// The number of bits available for the timestamp.
+        bits: u8,
+    },
+
+    /// A LocalTimestamp2 value is outside the range 1-6.
+    InvalidLocalTimestamp2(u8),
+
+    /// An Extension packet page number does not fit in three bits.
+    InvalidExtensionPage(u8),
+
+    /// A stimulus port is not on the current page: that of the last
+    /// Extension packet encoded, or page 0. Encode an Extension packet
+    /// of page `port / 32` first.
+    InvalidPort(u8),
+
+    /// An exception number does not fit in nine bits.
+    InvalidExceptionNumber(u16),
+
+    /// A DWT comparator number does not fit in two bits.
+    InvalidComparator(u8),
+
+    /// The payload of a source packet is of a size that cannot be
+    /// encoded for the packet type. See (Appendix D4.2.8, Table D4-4).
+    InvalidPayloadSize(usize),
+}
+
+impl fmt::Display for UnencodablePacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSyncLength(zeros) => write!(
+                f,
+                "A Synchronization packet of {} zeroes is too short or leaves the bitstream misaligned",
+                zeros
+            ),
+            Self::TimestampOverflow { ts, bits } => {
+                write!(f, "Timestamp {} does not fit in {} bits", ts, bits)
+            }
+            Self::InvalidLocalTimestamp2(ts) => write!(
+                f,
+                "LocalTimestamp2 value {} is outside the valid range 1-6",
+                ts
+            ),
+            Self::InvalidExtensionPage(page) => {
+                write!(f, "Extension packet page {} does not fit in 3 bits", page)
+            }
+            Self::InvalidPort(port) => write!(
+                f,
+                "Stimulus port {} is not on the page of the last Extension packet",
+                port
+            ),
+            Self::InvalidExceptionNumber(number) => {
+                write!(f, "Exception number {} does not fit in 9 bits", number)
+            }
+            Self::InvalidComparator(comparator) => {
+                write!(f, "DWT comparator {} does not fit in 2 bits", comparator)
+            }
+            Self::InvalidPayloadSize(len) => write!(
+                f,
+                "A payload of {} bytes cannot be encoded for this packet type",
+                len
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UnencodablePacket {}
+
+/// The width of global timestamps emitted by the target. Determines the
+/// payload size of [TracePacket::GlobalTimestamp2]. (Appendix D4.2.5)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum GlobalTimestampWidth {
+    /// A 48-bit timestamp; GTS2 carries bits\[47:26\].
+    #[default]
+    Bits48,
+
+    /// A 64-bit timestamp; GTS2 carries bits\[63:26\].
+    Bits64,
+}
+
+pub struct EncoderOptions {
+    /// Number of zero bits to emit for a [TracePacket::Sync]. Must be
+    /// at least 47, and one less than a multiple of eight so that the
+    /// bitstream remains byte-aligned after the terminating set bit.
+    pub sync_zeros: usize,
+
+    /// Width of the global timestamps encoded in
+    /// [TracePacket::GlobalTimestamp2] packets.
+    pub gts2_width: GlobalTimestampWidth,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        Self {
+            sync_zeros: SYNC_MIN_ZEROS,
+            gts2_width: GlobalTimestampWidth::default(),
+        }
+    }
+}
+
+/// ITM and DWT packet protocol encoder.
+pub struct Encoder {
+    /// Encoder options
+    options: EncoderOptions,
+
+    /// The stimulus port page of the last encoded Extension packet.
+    page: u8,
+}
+
+impl Encoder {
+    pub fn new(options: EncoderOptions) -> Self {
+        Encoder { options, page: 0 }
+    }
+
+    /// Encode a single [TracePacket] into its bitstream representation.
+    pub fn encode(&mut self, packet: &TracePacket) -> Result<Vec<u8>, UnencodablePacket> {
+        let mut buf = vec![];
+        self.encode_into(packet, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Encode a single [TracePacket] and append it to `buf`. Returns the
+    /// number of bytes appended. `buf` is left untouched on error.
+    pub fn encode_into(
+        &mut self,
+        packet: &TracePacket,
+        buf: &mut Vec<u8>,
+    ) -> Result<usize, UnencodablePacket> {
+        let len = buf.len();
+        match self.encode_packet(packet, buf) {
+            Ok(()) => Ok(buf.len() - len),
+            Err(e) => {
+                buf.truncate(len);
+                Err(e)
+            }
+        }
+    }
+
+    fn encode_packet(
+        &mut self,
+        packet: &TracePacket,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), UnencodablePacket> {
+        match packet {
+            // Synchronization packet category
+            TracePacket::Sync => {
+                let zeros = self.options.sync_zeros;
+                if zeros < SYNC_MIN_ZEROS || !(zeros + 1).is_multiple_of(8) {
+                    return Err(UnencodablePacket::InvalidSyncLength(zeros));
+                }
+
+                buf.resize(buf.len() + zeros / 8, 0);
+                buf.push(1 << 7);
+            }
+
+            // Protocol packet category
+            TracePacket::Overflow => buf.push(0b0111_0000),
+            TracePacket::LocalTimestamp1 { ts, data_relation } => {
+                let tc = match data_relation {
+                    TimestampDataRelation::Sync => 0b00,
+                    TimestampDataRelation::UnknownDelay => 0b01,
+                    TimestampDataRelation::AssocEventDelay => 0b10,
+                    TimestampDataRelation::UnknownAssocEventDelay => 0b11,
+                };
+                check_width(*ts, 28)?;
+
+                // Compress the timestamp into as few payload bytes as
+                // possible. (Appendix D4.2.4)
+                let mut cnt = 1;
+                while cnt < 4 && ts >> (7 * cnt) != 0 {
+                    cnt += 1;
+                }
+
+                buf.push(0b1100_0000 | (tc << 4));
+                push_continued(buf, *ts, cnt);
+            }
+            TracePacket::LocalTimestamp2 { ts } => {
+                if !(1..=6).contains(ts) {
+                    return Err(UnencodablePacket::InvalidLocalTimestamp2(*ts));
+                }
+
+                buf.push(ts << 4);
+            }
+            TracePacket::GlobalTimestamp1 { ts, wrap, clkch } => {
+                check_width(*ts, 26)?;
+
+                // The decoder reads the wrap and clkch flags from the
+                // last payload byte, so the full four bytes are always
+                // emitted.
+                buf.push(0b1001_0100);
+                push_continued(buf, *ts, 4);
+                *buf.last_mut().unwrap() |= ((*wrap as u8) << 6) | ((*clkch as u8) << 5);
+            }
+            TracePacket::GlobalTimestamp2 { ts } => {
+                let (bits, cnt) = match self.options.gts2_width {
+                    GlobalTimestampWidth::Bits48 => (48 - 26, 4),
+                    GlobalTimestampWidth::Bits64 => (64 - 26, 6),
+                };
+                check_width(*ts, bits)?;
+
+                buf.push(0b1011_0100);
+                push_continued(buf, *ts, cnt);
+            }
+            TracePacket::Extension { page } => {
+                if *page > 0b111 {
+                    return Err(UnencodablePacket::InvalidExtensionPage(*page));
+                }
+
+                buf.push((page << 4) | 0b1000);
+                self.page = *page;
+            }
+
+            // Source packet category
+            TracePacket::Instrumentation { port, payload } => {
+                // The page of ports above 31 is carried by a preceding
+                // Extension packet.
+                if port / 32 != self.page {
+                    return Err(UnencodablePacket::InvalidPort(*port));
+                }
+
+                buf.push(((port & 0b1_1111) << 3) | encode_ss(payload.len())?);
+                buf.extend_from_slice(payload);
+            }
+            TracePacket::EventCounterWrap {
+                cyc,
+                fold,
+                lsu,
+                sleep,
+                exc,
+                cpi,
+            } => push_hardware_source(
+                buf,
+                0,
+                &[(*cyc as u8) << 5
+                    | (*fold as u8) << 4
+                    | (*lsu as u8) << 3
+                    | (*sleep as u8) << 2
+                    | (*exc as u8) << 1
+                    | (*cpi as u8)],
+            )?,
+            TracePacket::ExceptionTrace { exception, action } => {
+                let number = exception.number();
+                if number > 0x1FF {
+                    return Err(UnencodablePacket::InvalidExceptionNumber(number));
+                }
+                let function = match action {
+                    ExceptionAction::Entered => 0b01,
+                    ExceptionAction::Exited => 0b10,
+                    ExceptionAction::Returned => 0b11,
+                };
+
+                push_hardware_source(
+                    buf,
+                    1,
+                    &[number as u8, (function << 4) | (number >> 8) as u8 & 1],
+                )?;
+            }
+            TracePacket::PCSample { pc: None } => push_hardware_source(buf, 2, &[0])?,
+            TracePacket::PCSample { pc: Some(pc) } => {
+                push_hardware_source(buf, 2, &pc.to_le_bytes())?
+            }
+            TracePacket::DataTracePC { comparator, pc } => push_hardware_source(
+                buf,
+                data_trace_disc(0b01, *comparator, 0)?,
+                &pc.to_le_bytes(),
+            )?,
+            TracePacket::DataTraceAddress { comparator, data } => {
+                if data.len() != 2 {
+                    return Err(UnencodablePacket::InvalidPayloadSize(data.len()));
+                }
+
+                push_hardware_source(buf, data_trace_disc(0b01, *comparator, 1)?, data)?
+            }
+            TracePacket::DataTraceValue {
+                comparator,
+                access_type,
+                access_size,
+                value,
+            } => {
+                if value.len() != access_size.bytes() {
+                    return Err(UnencodablePacket::InvalidPayloadSize(value.len()));
+                }
+
+                let d = match access_type {
+                    MemoryAccessType::Read => 0,
+                    MemoryAccessType::Write => 1,
+                };
+
+                push_hardware_source(buf, data_trace_disc(0b10, *comparator, d)?, value)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Verifies that `ts` fits in `bits` bits.
+fn check_width(ts: u64, bits: u8) -> Result<(), UnencodablePacket> {
+    if ts >> bits != 0 {
+        Err(UnencodablePacket::TimestampOverflow { ts, bits })
+    } else {
+        Ok(())
+    }
+}
+
+/// Appends `cnt` bytes of `value`, seven bits at a time, with the
+/// continuation-bit set on all but the last byte. The inverse of
+/// `Decoder::pull_payload`. (e.g. Appendix D4, Fig. D4-4)
+fn push_continued(buf: &mut Vec<u8>, value: u64, cnt: usize) {
+    for i in 0..cnt {
+        let b = ((value >> (7 * i)) & 0x7F) as u8;
+        buf.push(if i + 1 < cnt { b | (1 << 7) } else { b });
+    }
+}
+
+/// Translates a payload length into the size field of a source packet
+/// header. See (Appendix D4.2.8, Table D4-4).
+fn encode_ss(len: usize) -> Result<u8, UnencodablePacket> {
+    match len {
+        1 => Ok(0b01),
+        2 => Ok(0b10),
+        4 => Ok(0b11),
+        _ => Err(UnencodablePacket::InvalidPayloadSize(len)),
+    }
+}
+
+/// Constructs the discriminator ID of a data trace packet. (Appendix
+/// D4.3.4)
+fn data_trace_disc(t: u8, comparator: u8, d: u8) -> Result<u8, UnencodablePacket> {
+    if comparator > 0b11 {
+        return Err(UnencodablePacket::InvalidComparator(comparator));
+    }
+
+    Ok((t << 3) | (comparator << 1) | d)
+}
+
+fn push_hardware_source(
+    buf: &mut Vec<u8>,
+    disc_id: u8,
+    payload: &[u8],
+) -> Result<(), UnencodablePacket> {
+    buf.push((disc_id << 3) | 0b100 | encode_ss(payload.len())?);
+    buf.extend_from_slice(payload);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_continued() {
+        let mut buf = vec![];
+        super::push_continued(&mut buf, 0b11_0000001_1111111, 3);
+        assert_eq!(buf, [0b1111_1111, 0b1000_0001, 0b0000_0011]);
+    }
+
+    #[test]
+    fn encode_ss() {
+        assert_eq!(super::encode_ss(4), Ok(0b11));
+        assert_eq!(
+            super::encode_ss(3),
+            Err(UnencodablePacket::InvalidPayloadSize(3))
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Selection of the packets a [Decoder](crate::Decoder) returns. See
+//! [DecoderOptions::filter](crate::DecoderOptions::filter).
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+use crate::TracePacket;
+
+/// The kind of a [TracePacket], without its fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum PacketKind {
+    Sync,
+    Overflow,
+    LocalTimestamp1,
+    LocalTimestamp2,
+    GlobalTimestamp1,
+    GlobalTimestamp2,
+    Extension,
+    Instrumentation,
+    EventCounterWrap,
+    ExceptionTrace,
+    PCSample,
+    DataTracePC,
+    DataTraceAddress,
+    DataTraceValue,
+}
+
+impl PacketKind {
+    /// All packet kinds.
+    pub const ALL: [PacketKind; 14] = [
+        Self::Sync,
+        Self::Overflow,
+        Self::LocalTimestamp1,
+        Self::LocalTimestamp2,
+        Self::GlobalTimestamp1,
+        Self::GlobalTimestamp2,
+        Self::Extension,
+        Self::Instrumentation,
+        Self::EventCounterWrap,
+        Self::ExceptionTrace,
+        Self::PCSample,
+        Self::DataTracePC,
+        Self::DataTraceAddress,
+        Self::DataTraceValue,
+    ];
+
+    /// Whether packets of this kind are used to track timestamps: the
+    /// timestamp and Overflow packets.
+    pub(crate) fn is_timing(self) -> bool {
+        matches!(
+            self,
+            Self::Overflow
+                | Self::LocalTimestamp1
+                | Self::LocalTimestamp2
+                | Self::GlobalTimestamp1
+                | Self::GlobalTimestamp2
+        )
+    }
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+impl TracePacket {
+    /// Returns the kind of this packet.
+    pub fn kind(&self) -> PacketKind {
+        match self {
+            Self::Sync => PacketKind::Sync,
+            Self::Overflow => PacketKind::Overflow,
+            Self::LocalTimestamp1 { .. } => PacketKind::LocalTimestamp1,
+            Self::LocalTimestamp2 { .. } => PacketKind::LocalTimestamp2,
+            Self::GlobalTimestamp1 { .. } => PacketKind::GlobalTimestamp1,
+            Self::GlobalTimestamp2 { .. } => PacketKind::GlobalTimestamp2,
+            Self::Extension { .. } => PacketKind::Extension,
+            Self::Instrumentation { .. } => PacketKind::Instrumentation,
+            Self::EventCounterWrap { .. } => PacketKind::EventCounterWrap,
+            Self::ExceptionTrace { .. } => PacketKind::ExceptionTrace,
+            Self::PCSample { .. } => PacketKind::PCSample,
+            Self::DataTracePC { .. } => PacketKind::DataTracePC,
+            Self::DataTraceAddress { .. } => PacketKind::DataTraceAddress,
+            Self::DataTraceValue { .. } => PacketKind::DataTraceValue,
+        }
+    }
+}
+
+/// A set of packet kinds and stimulus ports. An Instrumentation packet
+/// matches if both [PacketKind::Instrumentation] and its stimulus port
+/// are in the set; any other packet matches if its kind is.
+///
+/// Stimulus ports are matched including the page of the last Extension
+/// packet (`page * 32 + port`), regardless of
+/// [DecoderOptions::raw_ports](crate::DecoderOptions::raw_ports).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct PacketFilter {
+    /// Bit `n` is set if the `n`th [PacketKind] matches.
+    kinds: u16,
+
+    /// Bit `n % 32` of word `n / 32` is set if stimulus port `n`
+    /// matches.
+    ports: [u32; 8],
+}
+
+impl PacketFilter {
+    /// Returns a filter that matches all packets.
+    pub fn all() -> Self {
+        Self {
+            kinds: PacketKind::ALL.iter().fold(0, |kinds, k| kinds | k.bit()),
+            ports: [u32::MAX; 8],
+        }
+    }
+
+    /// Returns a filter that matches no packets.
+    pub fn none() -> Self {
+        Self {
+            kinds: 0,
+            ports: [0; 8],
+        }
+    }
+
+    /// Adds a packet kind to the filter.
+    pub fn with_kind(mut self, kind: PacketKind) -> Self {
+        self.kinds |= kind.bit();
+        self
+    }
+
+    /// Removes a packet kind from the filter.
+    pub fn without_kind(mut self, kind: PacketKind) -> Self {
+        self.kinds &= !kind.bit();
+        self
+    }
+
+    /// Adds a stimulus port, and [PacketKind::Instrumentation], to the
+    /// filter.
+    pub fn with_port(mut self, port: u8) -> Self {
+        self.ports[port as usize / 32] |= 1 << (port % 32);
+        self.with_kind(PacketKind::Instrumentation)
+    }
+
+    /// Removes a stimulus port from the filter.
+    pub fn without_port(mut self, port: u8) -> Self {
+        self.ports[port as usize / 32] &= !(1 << (port % 32));
+        self
+    }
+
+    /// Returns whether packets of the given kind match, disregarding
+    /// the stimulus port of Instrumentation packets.
+    pub fn matches_kind(&self, kind: PacketKind) -> bool {
+        self.kinds & kind.bit() != 0
+    }
+
+    /// Returns whether Instrumentation packets from the given stimulus
+    /// port match.
+    pub fn matches_port(&self, port: u8) -> bool {
+        self.matches_kind(PacketKind::Instrumentation)
+            && self.ports[port as usize / 32] & (1 << (port % 32)) != 0
+    }
+
+    /// Returns whether the packet matches. The port of an
+    /// Instrumentation packet is assumed to include its page.
+    pub fn matches(&self, packet: &TracePacket) -> bool {
+        match packet {
+            TracePacket::Instrumentation { port, .. } => self.matches_port(*port),
+            packet => self.matches_kind(packet.kind()),
+        }
+    }
+}
+
+impl Default for PacketFilter {
+    /// Matches all packets.
+    fn default() -> Self {
+        Self::all()
+    }
+}
@@ -10,52 +10,73 @@
 //! - DWT: data watchpoint and trace unit;
 //! - MSB: most significant bit;
 //! - BE: big-endian;
+//!
+//! The crate is `#![no_std]` but requires `alloc`. `std` support,
+//! which enables `std::error::Error` implementations and decoding
+//! directly from `std::io::Read` sources, is enabled by default via the
+//! `std` feature. The `tokio` feature provides a
+//! `tokio_util::codec::Decoder`, and the `futures` feature a
+//! `futures_core::Stream` over `futures_io::AsyncRead` sources. The
+//! `cortex-m` feature provides conversions between [ExceptionType] and
+//! the exception types of the `cortex-m` crate.
+
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+mod encoder;
+pub use encoder::{Encoder, EncoderOptions, GlobalTimestampWidth, UnencodablePacket};
+
+mod payload;
+pub use payload::{AccessSize, Payload, PayloadValue};
+
+mod filter;
+pub use filter::{PacketFilter, PacketKind};
+
+mod stats;
+pub use stats::{DecoderStats, MalformedKind};
+
+mod timeline;
+pub use timeline::{Discontinuity, Timeline, TimelineGroup};
 
+mod timing;
+pub use timing::{TimingConfig, WallClock};
+
+mod options;
+pub use options::{DecoderOptionsBuilder, InvalidDecoderOptions, LocalTimestampPrescaler};
+
+#[cfg(feature = "std")]
+mod reader;
+#[cfg(feature = "std")]
+pub use reader::{ItmReader, TimestampedItmReader};
+
+#[cfg(feature = "tokio")]
+mod codec;
+#[cfg(feature = "tokio")]
+pub use codec::{ItmCodec, TimestampedItmCodec};
+
+#[cfg(feature = "futures")]
+mod stream;
+#[cfg(feature = "futures")]
+pub use stream::{ItmStream, TimestampedItmStream};
+
+use alloc::{collections::VecDeque, vec, vec::Vec};
 use bitmatch::bitmatch;
-use bitvec::prelude::*;
-use std::convert::TryInto;
+use core::convert::TryInto;
+use core::fmt;
 
 #[cfg(feature = "serde")]
 use serde_crate::{Deserialize, Serialize};
 
-/// Re-exports for exception types of the `cortex-m` crate for `serde`
-/// purposes.
+/// Re-exports of the exception types of the `cortex-m` crate that
+/// [ExceptionType] converts to and from.
+#[cfg(feature = "cortex-m")]
 pub mod cortex_m {
     /// Denotes the exception type (interrupt event) of the processor.
     /// (Table B1-4)
     pub use cortex_m::peripheral::scb::{Exception, VectActive};
-
-    /// Verbatim copy of used `cortex_m` enums for serde functionality.
-    /// Should not be used directly. Public because serde requires it.
-    /// See <https://serde.rs/remote-derive.html>
-    #[cfg(feature = "serde")]
-    pub mod serde {
-        use super::{Exception, VectActive};
-        use serde_crate::{Deserialize, Serialize};
-
-        #[derive(Serialize, Deserialize)]
-        #[serde(crate = "serde_crate", remote = "Exception")]
-        pub enum ExceptionDef {
-            NonMaskableInt,
-            HardFault,
-            MemoryManagement,
-            BusFault,
-            UsageFault,
-            SecureFault,
-            SVCall,
-            DebugMonitor,
-            PendSV,
-            SysTick,
-        }
-
-        #[derive(Serialize, Deserialize)]
-        #[serde(crate = "serde_crate", remote = "VectActive")]
-        pub enum VectActiveDef {
-            ThreadMode,
-            Exception(#[serde(with = "ExceptionDef")] Exception),
-            Interrupt { irqn: u8 },
-        }
-    }
 }
 
 /// The set of valid packet types that can be decoded.
@@ -76,9 +97,9 @@ pub enum TracePacket {
     /// Found in the bitstream if
     ///
     /// - Software has written to an ITM stimulus port register when the
-    /// stimulus port output buffer is full.
+    ///   stimulus port output buffer is full.
     /// - The DWT attempts to generate a hardware source packet when the
-    /// DWT output buffer is full.
+    ///   DWT output buffer is full.
     /// - The local timestamp counter overflows.
     ///
     /// See (Appendix D4.2.3).
@@ -138,11 +159,14 @@ pub enum TracePacket {
     // Source packet category
     /// Contains the payload written to the ITM stimulus ports.
     Instrumentation {
-        /// Stimulus port number.
+        /// Stimulus port number. Includes the page of the last
+        /// [TracePacket::Extension] packet unless
+        /// [DecoderOptions::raw_ports] is set.
         port: u8,
 
         /// Instrumentation data written to the stimulus port. MSB, BE.
-        payload: Vec<u8>,
+        /// See [Payload::value].
+        payload: Payload,
     },
 
     /// One or more event counters have wrapped. (Appendix D4.3.1)
@@ -164,8 +188,7 @@ pub enum TracePacket {
     /// The processor has entered, exit, or returned to an exception.
     /// (Appendix D4.3.2)
     ExceptionTrace {
-        #[cfg_attr(feature = "serde", serde(with = "cortex_m::serde::VectActiveDef"))]
-        exception: cortex_m::VectActive,
+        exception: ExceptionType,
         action: ExceptionAction,
     },
 
@@ -191,7 +214,7 @@ pub enum TracePacket {
         comparator: u8,
 
         /// Data address content; bits\[15:0\]. MSB, BE.
-        data: Vec<u8>,
+        data: Payload,
     },
 
     /// A data trace packet with a value. (Appendix D4.3.4)
@@ -202,11 +225,254 @@ pub enum TracePacket {
         /// Whether the data was read or written.
         access_type: MemoryAccessType,
 
-        /// The data value. MSB, BE.
-        value: Vec<u8>,
+        /// The size of the access; the width of `value`.
+        access_size: AccessSize,
+
+        /// The data value. MSB, BE. See [Payload::value].
+        value: Payload,
+    },
+}
+
+/// Formats packets on a single line, with PCs and addresses in
+/// hexadecimal. The alternate form (`{:#}`) includes all fields.
+impl fmt::Display for TracePacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alt = f.alternate();
+        match self {
+            Self::Sync => write!(f, "Sync"),
+            Self::Overflow => write!(f, "Overflow"),
+            Self::LocalTimestamp1 { ts, data_relation } => {
+                write!(f, "Local timestamp {}", ts)?;
+                if alt || *data_relation != TimestampDataRelation::Sync {
+                    write!(f, " ({})", data_relation)?;
+                }
+                Ok(())
+            }
+            Self::LocalTimestamp2 { ts } => write!(f, "Local timestamp {}", ts),
+            Self::GlobalTimestamp1 { ts, wrap, clkch } => {
+                write!(f, "Global timestamp lower bits {:#x}", ts)?;
+                if alt {
+                    write!(f, " (wrap: {}, clock change: {})", wrap, clkch)
+                } else {
+                    for (set, flag) in [(wrap, "wrap"), (clkch, "clock change")] {
+                        if *set {
+                            write!(f, ", {}", flag)?;
+                        }
+                    }
+                    Ok(())
+                }
+            }
+            Self::GlobalTimestamp2 { ts } => write!(f, "Global timestamp upper bits {:#x}", ts),
+            Self::Extension { page } => write!(f, "Extension, stimulus port page {}", page),
+            Self::Instrumentation { port, payload } => {
+                write!(f, "Instrumentation, port {}: ", port)?;
+                fmt_payload(f, payload)
+            }
+            Self::EventCounterWrap {
+                cyc,
+                fold,
+                lsu,
+                sleep,
+                exc,
+                cpi,
+            } => {
+                write!(f, "Event counter wrap:")?;
+                let counters = [
+                    (cyc, "CYC"),
+                    (fold, "FOLD"),
+                    (lsu, "LSU"),
+                    (sleep, "SLEEP"),
+                    (exc, "EXC"),
+                    (cpi, "CPI"),
+                ];
+                for (wrapped, counter) in counters {
+                    if alt {
+                        write!(f, " {}{}", if *wrapped { '+' } else { '-' }, counter)?;
+                    } else if *wrapped {
+                        write!(f, " {}", counter)?;
+                    }
+                }
+                Ok(())
+            }
+            Self::ExceptionTrace { exception, action } => {
+                write!(f, "Exception {}", exception)?;
+                if alt {
+                    write!(f, " (number {})", exception.number())?;
+                }
+                write!(f, " {}", action)
+            }
+            Self::PCSample { pc: Some(pc) } => write!(f, "PC sample {:#010x}", pc),
+            Self::PCSample { pc: None } => write!(f, "PC sample, sleeping"),
+            Self::DataTracePC { comparator, pc } => {
+                write!(f, "Data trace, comparator {}: PC {:#010x}", comparator, pc)
+            }
+            Self::DataTraceAddress { comparator, data } => {
+                write!(f, "Data trace, comparator {}: address ", comparator)?;
+                fmt_payload(f, data)
+            }
+            Self::DataTraceValue {
+                comparator,
+                access_type,
+                access_size,
+                value,
+            } => {
+                write!(f, "Data trace, comparator {}: {} ", comparator, access_type)?;
+                if alt {
+                    write!(f, "{:?} ", access_size)?;
+                }
+                fmt_payload(f, value)
+            }
+        }
+    }
+}
+
+/// Formats a payload as a hexadecimal number of its width. The
+/// alternate form also includes the decimal value and the bytes of the
+/// payload.
+fn fmt_payload<const N: usize>(f: &mut fmt::Formatter<'_>, payload: &Payload<N>) -> fmt::Result {
+    match payload.value() {
+        Some(value) => write!(f, "{:#x}", value)?,
+        None => return write!(f, "{}", HexBytes(payload)),
+    }
+    if f.alternate() {
+        write!(
+            f,
+            " ({}, bytes {})",
+            payload.value().unwrap().as_u32(),
+            HexBytes(payload)
+        )?;
+    }
+    Ok(())
+}
+
+/// Formats bytes as space-separated hexadecimal numbers in brackets.
+struct HexBytes<'a>(&'a [u8]);
+
+impl fmt::Display for HexBytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, b) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Denotes the exception type (interrupt event) of the processor by its
+/// 9-bit exception number. (Table B1-4)
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum ExceptionType {
+    /// The processor is in Thread mode; exception number 0.
+    ThreadMode,
+    Reset,
+    NonMaskableInt,
+    HardFault,
+    MemoryManagement,
+    BusFault,
+    UsageFault,
+
+    /// Reserved on ARMv7-M.
+    SecureFault,
+    SVCall,
+    DebugMonitor,
+    PendSV,
+    SysTick,
+
+    /// A reserved exception number: 8-10 or 13.
+    Reserved(u16),
+
+    /// An external interrupt; exception number `16 + irqn`.
+    Interrupt {
+        /// Interrupt number, 0-495.
+        irqn: u16,
     },
 }
 
+impl ExceptionType {
+    /// Returns the exception type of the given exception number, or
+    /// `None` if it does not fit in 9 bits.
+    pub fn from_number(number: u16) -> Option<Self> {
+        Some(match number {
+            0 => Self::ThreadMode,
+            1 => Self::Reset,
+            2 => Self::NonMaskableInt,
+            3 => Self::HardFault,
+            4 => Self::MemoryManagement,
+            5 => Self::BusFault,
+            6 => Self::UsageFault,
+            7 => Self::SecureFault,
+            11 => Self::SVCall,
+            12 => Self::DebugMonitor,
+            14 => Self::PendSV,
+            15 => Self::SysTick,
+            8..=10 | 13 => Self::Reserved(number),
+            16..=511 => Self::Interrupt { irqn: number - 16 },
+            _ => return None,
+        })
+    }
+
+    /// Returns the exception number of this exception type.
+    pub fn number(&self) -> u16 {
+        match self {
+            Self::ThreadMode => 0,
+            Self::Reset => 1,
+            Self::NonMaskableInt => 2,
+            Self::HardFault => 3,
+            Self::MemoryManagement => 4,
+            Self::BusFault => 5,
+            Self::UsageFault => 6,
+            Self::SecureFault => 7,
+            Self::SVCall => 11,
+            Self::DebugMonitor => 12,
+            Self::PendSV => 14,
+            Self::SysTick => 15,
+            Self::Reserved(number) => *number,
+            Self::Interrupt { irqn } => irqn + 16,
+        }
+    }
+
+    /// Converts this exception type into the equivalent
+    /// [cortex_m::VectActive], if any. Reset, reserved exception
+    /// numbers, and interrupts above 239 have no equivalent.
+    #[cfg(feature = "cortex-m")]
+    pub fn to_vect_active(&self) -> Option<cortex_m::VectActive> {
+        match self {
+            Self::Reset | Self::Reserved(_) => None,
+            _ => cortex_m::VectActive::from(self.number().try_into().ok()?),
+        }
+    }
+}
+
+impl fmt::Display for ExceptionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reserved(number) => write!(f, "Reserved({})", number),
+            Self::Interrupt { irqn } => write!(f, "IRQ{}", irqn),
+            exception => write!(f, "{:?}", exception),
+        }
+    }
+}
+
+#[cfg(feature = "cortex-m")]
+impl From<cortex_m::VectActive> for ExceptionType {
+    fn from(vect: cortex_m::VectActive) -> Self {
+        let number = match vect {
+            cortex_m::VectActive::ThreadMode => 0,
+            cortex_m::VectActive::Exception(ex) => (ex.irqn() + 16) as u16,
+            cortex_m::VectActive::Interrupt { irqn } => irqn as u16 + 16,
+        };
+        Self::from_number(number).unwrap()
+    }
+}
+
 /// Denotes the action taken by the processor by a given exception. (Table D4-6)
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(
@@ -225,6 +491,16 @@ pub enum ExceptionAction {
     Returned,
 }
 
+impl fmt::Display for ExceptionAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Entered => write!(f, "entered"),
+            Self::Exited => write!(f, "exited"),
+            Self::Returned => write!(f, "returned"),
+        }
+    }
+}
+
 /// Denotes the type of memory access.
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(
@@ -240,6 +516,15 @@ pub enum MemoryAccessType {
     Write,
 }
 
+impl fmt::Display for MemoryAccessType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Write => write!(f, "write"),
+        }
+    }
+}
+
 /// Indicates the relationship between the generation of the local
 /// timestamp packet and the corresponding ITM or DWT data packet.
 /// (Appendix D4.2.4)
@@ -281,8 +566,19 @@ pub enum TimestampDataRelation {
     UnknownAssocEventDelay,
 }
 
+impl fmt::Display for TimestampDataRelation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sync => write!(f, "synchronous"),
+            Self::UnknownDelay => write!(f, "timestamp delayed"),
+            Self::AssocEventDelay => write!(f, "packet delayed"),
+            Self::UnknownAssocEventDelay => write!(f, "timestamp and packet delayed"),
+        }
+    }
+}
+
 /// A header or payload byte failed to be decoded.
-#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
@@ -290,23 +586,20 @@ pub enum TimestampDataRelation {
 )]
 pub enum MalformedPacket {
     /// Header is invalid and cannot be decoded.
-    #[error("Header is invalid and cannot be decoded: {}", format!("{:#b}", .0))]
     InvalidHeader(u8),
 
     /// The type discriminator ID in the hardware source packet header
     /// is invalid or the associated payload is of wrong size.
-    #[error("Hardware source packet type discriminator ID ({disc_id}) or payload length ({}) is invalid", .payload.len())]
     InvalidHardwarePacket {
         /// The discriminator ID. Potentially invalid.
         disc_id: u8,
 
         /// Associated payload. Potentially invalid length. MSB, BE.
-        payload: Vec<u8>,
+        payload: Payload,
     },
 
     /// The type discriminator ID in the hardware source packet header
     /// is invalid.
-    #[error("Hardware source packet discriminator ID is invalid: {disc_id}")]
     InvalidHardwareDisc {
         /// The discriminator ID. Potentially invalid.
         disc_id: u8,
@@ -315,9 +608,7 @@ pub enum MalformedPacket {
         size: usize,
     },
 
-    /// An exception trace packet refers to an invalid action or an
-    /// invalid exception number.
-    #[error("IRQ number {exception} and/or action {function} is invalid")]
+    /// An exception trace packet refers to an invalid action.
     InvalidExceptionTrace {
         /// The exception number.
         exception: u16,
@@ -328,33 +619,66 @@ pub enum MalformedPacket {
     },
 
     /// The payload length of a PCSample packet is invalid.
-    #[error("Payload length of PC sample is invalid: {}", .payload.len())]
     InvalidPCSampleSize {
         /// The payload constituting the PC value, of invalid size. MSB, BE.
-        payload: Vec<u8>,
+        payload: Payload,
     },
 
     /// The GlobalTimestamp2 packet does not contain a 48-bit or 64-bit
-    /// timestamp.
-    #[error("GlobalTimestamp2 packet does not contain a 48-bit or 64-bit timestamp")]
+    /// timestamp, or not of the width in [DecoderOptions::gts2_width].
     InvalidGTS2Size {
-        /// The payload constituting the timestamp, of invalid size. MSB, BE.
-        payload: Vec<u8>,
+        /// The payload constituting the timestamp, of invalid size.
+        /// Truncated to the first seven bytes. MSB, BE.
+        payload: Payload<7>,
+    },
+
+    /// The payload of a LocalTimestamp1 or GlobalTimestamp1 packet does
+    /// not end within four bytes. (Appendix D4.2.4, D4.2.5)
+    InvalidTimestampSize {
+        /// The kind of the timestamp packet.
+        kind: PacketKind,
+
+        /// The first four bytes of the payload, all with the
+        /// continuation-bit set.
+        payload: Payload,
     },
 
     /// The number of zeroes in the Synchronization packet is less than
-    /// 47.
-    #[error(
-        "The number of zeroes in the Synchronization packet is less than expected: {0} < {}",
-        SYNC_MIN_ZEROS
-    )]
+    /// [DecoderOptions::sync_min_zeros].
     InvalidSync(usize),
 
+    /// An Instrumentation packet is from a stimulus port not
+    /// implemented by the target. See [DecoderOptions::stimulus_ports].
+    InvalidStimulusPort(u16),
+
+    /// A data trace packet is from a DWT comparator not implemented by
+    /// the target. See [DecoderOptions::comparators].
+    InvalidComparator(u8),
+
+    /// A hardware source packet sets bits that are reserved: bits\[7:6\]
+    /// of an event counter packet, bits\[7:6\] and \[3:1\] of the second
+    /// byte of an exception trace packet, or any bit of a PC sleep
+    /// packet. See [ValidationMode].
+    ReservedBits {
+        /// The discriminator ID.
+        disc_id: u8,
+
+        /// Associated payload. MSB, BE.
+        payload: Payload,
+    },
+
+    /// A data trace PC value or address packet has a payload size that
+    /// is reserved for its type. (Appendix D4.3.4) See [ValidationMode].
+    ReservedEncoding {
+        /// The discriminator ID.
+        disc_id: u8,
+
+        /// Associated payload. MSB, BE.
+        payload: Payload,
+    },
+
     /// A source packet (from software or hardware) contains an invalid
     /// expected payload size.
-    #[error(
-        "A source packet (from software or hardware) contains an invalid expected payload size"
-    )]
     InvalidSourcePayload {
         /// The header which contains the invalid payload size.
         header: u8,
@@ -364,12 +688,148 @@ pub enum MalformedPacket {
     },
 }
 
+impl fmt::Display for MalformedPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHeader(header) => {
+                write!(f, "Header is invalid and cannot be decoded: {:#b}", header)
+            }
+            Self::InvalidHardwarePacket { disc_id, payload } => write!(
+                f,
+                "Hardware source packet type discriminator ID ({}) or payload length ({}) is invalid",
+                disc_id,
+                payload.len()
+            ),
+            Self::InvalidHardwareDisc { disc_id, .. } => write!(
+                f,
+                "Hardware source packet discriminator ID is invalid: {}",
+                disc_id
+            ),
+            Self::InvalidExceptionTrace {
+                exception,
+                function,
+            } => write!(
+                f,
+                "IRQ number {} and/or action {} is invalid",
+                exception, function
+            ),
+            Self::InvalidPCSampleSize { payload } => write!(
+                f,
+                "Payload length of PC sample is invalid: {}",
+                payload.len()
+            ),
+            Self::InvalidGTS2Size { .. } => write!(
+                f,
+                "GlobalTimestamp2 packet does not contain a 48-bit or 64-bit timestamp"
+            ),
+            Self::InvalidTimestampSize { kind, payload } => write!(
+                f,
+                "{:?} packet payload does not end within {} bytes",
+                kind,
+                payload.len()
+            ),
+            Self::InvalidSync(count) => write!(
+                f,
+                "The number of zeroes in the Synchronization packet is less than expected: {}",
+                count
+            ),
+            Self::InvalidStimulusPort(port) => {
+                write!(f, "Stimulus port {} is not implemented", port)
+            }
+            Self::InvalidComparator(comparator) => {
+                write!(f, "DWT comparator {} is not implemented", comparator)
+            }
+            Self::ReservedBits { disc_id, .. } => write!(
+                f,
+                "Hardware source packet (discriminator ID {}) sets reserved bits",
+                disc_id
+            ),
+            Self::ReservedEncoding { disc_id, payload } => write!(
+                f,
+                "Data trace packet (discriminator ID {}) has reserved payload length {}",
+                disc_id,
+                payload.len()
+            ),
+            Self::InvalidSourcePayload { .. } => write!(
+                f,
+                "A source packet (from software or hardware) contains an invalid expected payload size"
+            ),
+        }?;
+
+        // Alternate form: include the data the packet was rejected for
+        if !f.alternate() {
+            return Ok(());
+        }
+        match self {
+            Self::InvalidHardwarePacket { disc_id, payload }
+            | Self::ReservedBits { disc_id, payload }
+            | Self::ReservedEncoding { disc_id, payload } => write!(
+                f,
+                " (discriminator ID {}, payload {})",
+                disc_id,
+                HexBytes(payload)
+            ),
+            Self::InvalidHardwareDisc { size, .. } => write!(f, " (payload size {})", size),
+            Self::InvalidPCSampleSize { payload } => write!(f, " (payload {})", HexBytes(payload)),
+            Self::InvalidGTS2Size { payload } => write!(f, " (payload {})", HexBytes(payload)),
+            Self::InvalidTimestampSize { payload, .. } => {
+                write!(f, " (payload {})", HexBytes(payload))
+            }
+            Self::InvalidSourcePayload { header, size } => {
+                write!(f, " (header {:#010b}, size {:#04b})", header, size)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MalformedPacket {}
+
 const SYNC_MIN_ZEROS: usize = 47;
 
+/// Maximum number of stimulus ports an ITM implements. (Appendix
+/// D4.2.6)
+const MAX_STIMULUS_PORTS: u16 = 256;
+
+/// Maximum number of DWT comparators that can generate data trace
+/// packets. (Appendix D4.3.4)
+const MAX_COMPARATORS: u8 = 4;
+
+/// Maximum payload size of a LocalTimestamp1 or GlobalTimestamp1
+/// packet. (Appendix D4.2.4, D4.2.5)
+const MAX_TS_PAYLOAD: usize = 4;
+
+/// Maximum payload size of a GlobalTimestamp2 packet, of a 64-bit
+/// timestamp. (Appendix D4.2.5)
+const MAX_GTS2_PAYLOAD: usize = 6;
+
+/// Number of candidate header alignments considered by
+/// [RecoveryPolicy::Heuristic].
+const RESYNC_WINDOW: usize = 16;
+
+/// Number of bytes that must be available before
+/// [RecoveryPolicy::Heuristic] scores the candidate alignments.
+const RESYNC_LOOKAHEAD: usize = 32;
+
+/// Number of consecutive packets a candidate alignment must decode to
+/// achieve the maximum score.
+const RESYNC_PACKETS: usize = 4;
+
+/// Number of most recent warnings retained by the decoder until
+/// [Decoder::take_warnings] is called.
+const MAX_WARNINGS: usize = 32;
+
 /// The decoder's possible states. The default decoder state is `Header`
 /// and will always return there after a maximum of two steps. (E.g. if
 /// the current state is `Syncing` or `HardwareSource`, the next state
 /// is `Header` again.)
+#[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
 enum PacketStub {
     /// Next zero bits will be assumed to be part of a a Synchronization
     /// packet until a set bit is encountered.
@@ -396,12 +856,21 @@ enum PacketStub {
     /// Next bytes will be assumed to be part of a GlobalTimestamp2
     /// packet, until the MSB is set.
     GlobalTimestamp2,
+
+    /// Next bytes will be discarded as the payload of a packet of
+    /// `kind` that does not match [DecoderOptions::filter]:
+    /// `expected_size` bytes, or until the MSB is not set if `None`.
+    Skip {
+        kind: PacketKind,
+        expected_size: Option<usize>,
+    },
 }
 
 /// Combined timestamp generated from local and global timestamp
-/// packets. Field values relate to the target's global timestamp clock.
-/// See (Appendix C1, page 713).
-#[derive(Debug, Clone, PartialEq)]
+/// packets. The base counts global timestamp clock cycles, and the
+/// delta trace clock cycles; see [TimingConfig] to convert them into
+/// time. See (Appendix C1, page 713).
+#[derive(Debug, Clone, PartialEq, Default)]
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
@@ -435,24 +904,54 @@ pub struct Timestamp {
     pub diverged: bool,
 }
 
-impl Default for Timestamp {
-    fn default() -> Self {
-        Timestamp {
-            base: None,
-            delta: None,
-            data_relation: None,
-            diverged: false,
+/// Formats the timestamp as the sum of its base and delta, or as
+/// `+delta` if there is no base. The alternate form (`{:#}`) also
+/// includes the base, delta and data relation separately.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.base, self.delta) {
+            (Some(base), delta) => write!(f, "{}", base.saturating_add(delta.unwrap_or(0)))?,
+            (None, Some(delta)) => write!(f, "+{}", delta)?,
+            (None, None) => write!(f, "?")?,
         }
+
+        if f.alternate() {
+            let mut sep = " (";
+            for (name, value) in [("base", self.base), ("delta", self.delta)] {
+                match value {
+                    Some(value) => write!(f, "{}{} {}", sep, name, value)?,
+                    None => write!(f, "{}no {}", sep, name)?,
+                }
+                sep = ", ";
+            }
+            if let Some(data_relation) = &self.data_relation {
+                write!(f, ", {}", data_relation)?;
+            }
+            write!(f, ")")?;
+        }
+        if self.diverged {
+            write!(f, ", diverged")?;
+        }
+        Ok(())
     }
 }
 
 /// A context in which to record the current timestamp between calls to [Decoder::pull_with_timestamp].
+#[derive(Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
 struct TimestampedContext {
     /// Data packets associated with [TimestampedContext::ts] in this structure.
     pub packets: Vec<TracePacket>,
 
     /// Malformed packets associated with [TimestampedContext::ts] in this structure.
-    pub malformed_packets: Vec<MalformedPacket>,
+    pub malformed_packets: Vec<Located<MalformedPacket>>,
+
+    /// Raw data of [TimestampedContext::packets], if retained.
+    pub raw: Vec<RawPacket>,
 
     /// The potentially received [TracePacket::GlobalTimestamp1] packet.
     /// Used in combination with [TimestampedContext::gts2] to update
@@ -467,51 +966,265 @@ struct TimestampedContext {
     /// The current timestamp.
     pub ts: Timestamp,
 
+    /// The timestamp of the last set of packets associated with a
+    /// local timestamp.
+    pub previous: Timestamp,
+
+    /// Whether the input clock to the ITM has changed since the last
+    /// set of packets was returned.
+    pub clock_changed: bool,
+
     /// Number of ITM packets consumed thus far.
     pub packets_consumed: usize,
+
+    /// Where the first packet consumed thus far starts in the bitstream.
+    pub start: Option<StreamPosition>,
+
+    /// Where the last packet consumed thus far ends in the bitstream.
+    pub end: StreamPosition,
 }
 
-impl Default for TimestampedContext {
-    fn default() -> Self {
-        TimestampedContext {
-            packets: vec![],
-            malformed_packets: vec![],
-            gts1: None,
-            gts2: None,
-            ts: Timestamp::default(),
-            packets_consumed: 0,
-        }
+impl TimestampedContext {
+    /// Stashes a data packet, and its raw data if retained.
+    fn push(&mut self, packet: TracePacket, raw: Option<RawPacket>) {
+        self.packets.push(packet);
+        self.raw.extend(raw);
     }
 }
 
+/// Decoder options, including the trace configuration of the target.
+/// Construct with [DecoderOptions::builder] to validate the parameters.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
 pub struct DecoderOptions {
     /// Whether to only process global timestamps in the bitstream on
     /// [Decoder::pull_with_timestamps].
     pub only_gts: bool,
+
+    /// How to find the next packet after a [MalformedPacket].
+    pub recovery: RecoveryPolicy,
+
+    /// Whether to report the stimulus port of
+    /// [TracePacket::Instrumentation] packets as encoded in the packet
+    /// header (0-31), instead of combined with the page of the last
+    /// [TracePacket::Extension] packet (`page * 32 + port`).
+    pub raw_ports: bool,
+
+    /// Whether to retain the data each packet is decoded from and
+    /// return it in [Located::raw].
+    pub retain_raw: bool,
+
+    /// Maximum number of bytes to buffer in the decoder. Unbounded if
+    /// `None`. See [Decoder::push]. Must be large enough to hold the
+    /// largest packet payload while the bitstream is misaligned, 7
+    /// bytes, and 33 bytes for [RecoveryPolicy::Heuristic]; otherwise
+    /// decoding may stall with a full buffer.
+    pub capacity: Option<usize>,
+
+    /// Width of the global timestamps emitted by the target. If `None`,
+    /// both 48-bit and 64-bit [TracePacket::GlobalTimestamp2] packets
+    /// are accepted.
+    pub gts2_width: Option<GlobalTimestampWidth>,
+
+    /// The local timestamp prescaler configured on the target. Local
+    /// timestamps are scaled by it in [Timestamp::delta].
+    pub lts_prescaler: LocalTimestampPrescaler,
+
+    /// Number of stimulus ports implemented by the target. Packets from
+    /// other ports are reported as [MalformedPacket::InvalidStimulusPort].
+    pub stimulus_ports: u16,
+
+    /// Number of DWT comparators implemented by the target. Data trace
+    /// packets from other comparators are reported as
+    /// [MalformedPacket::InvalidComparator].
+    pub comparators: u8,
+
+    /// Minimum number of zeros in a Synchronization packet.
+    pub sync_min_zeros: usize,
+
+    /// The packets to return. Packets that do not match are consumed,
+    /// and still update the stimulus port page and the synchronization
+    /// of the bitstream, but are not returned by [Decoder::pull] or
+    /// [Decoder::pull_with_timestamp]. Timestamp and Overflow packets
+    /// that do not match still contribute to the [Timestamp]s of
+    /// [Decoder::pull_with_timestamp].
+    pub filter: PacketFilter,
+
+    /// How to handle packets that set reserved bits or use reserved
+    /// encodings. Defaults to [ValidationMode::Lenient].
+    ///
+    /// This is a breaking change: packets previously rejected are now
+    /// decoded with a warning. E.g. a data trace PC value packet of one
+    /// byte (`[0x45, 0x12]`) was rejected as
+    /// [MalformedPacket::InvalidHardwarePacket], and a one-byte PC
+    /// sample with a non-zero payload as
+    /// [MalformedPacket::InvalidPCSampleSize]. [ValidationMode::Strict]
+    /// rejects both, as [MalformedPacket::ReservedEncoding] and
+    /// [MalformedPacket::ReservedBits] respectively.
+    pub validation: ValidationMode,
 }
 
 impl Default for DecoderOptions {
     fn default() -> Self {
-        Self { only_gts: false }
+        Self {
+            only_gts: false,
+            recovery: RecoveryPolicy::default(),
+            raw_ports: false,
+            retain_raw: false,
+            capacity: None,
+            gts2_width: None,
+            lts_prescaler: LocalTimestampPrescaler::default(),
+            stimulus_ports: MAX_STIMULUS_PORTS,
+            comparators: MAX_COMPARATORS,
+            sync_min_zeros: SYNC_MIN_ZEROS,
+            filter: PacketFilter::default(),
+            validation: ValidationMode::default(),
+        }
     }
 }
 
+/// Data could not be pushed into a [Decoder] because its input buffer
+/// is full. See [DecoderOptions::capacity].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferFull;
+
+impl fmt::Display for BufferFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Decoder input buffer is full")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BufferFull {}
+
+/// How the decoder recovers after a [MalformedPacket] has been
+/// decoded. Bytes discarded during recovery are counted by
+/// [Decoder::bytes_discarded].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum RecoveryPolicy {
+    /// Assume the byte following the malformed packet is the header of
+    /// the next packet.
+    #[default]
+    Naive,
+
+    /// Discard all data until the next Synchronization packet.
+    SkipToSync,
+
+    /// Score the next few byte offsets by how many consecutive packets
+    /// decode from each of them, and resume at the best one. Decoding
+    /// resumes once 32 bytes are available after the malformed packet,
+    /// or once the end of the input is signalled by [Decoder::finish].
+    Heuristic,
+}
+
+/// How the decoder handles packets that set bits or use encodings the
+/// architecture reserves, but that can otherwise be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum ValidationMode {
+    /// Report violations as [MalformedPacket::ReservedBits] or
+    /// [MalformedPacket::ReservedEncoding].
+    Strict,
+
+    /// Decode the packet as best as possible, ignoring reserved bits.
+    /// Violations are recorded as warnings; see
+    /// [Decoder::take_warnings]. Tolerates off-spec data from debug
+    /// probes and targets.
+    #[default]
+    Lenient,
+}
+
 /// ITM and DWT packet protocol decoder.
+#[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
 pub struct Decoder {
     /// Decoder options
     options: DecoderOptions,
 
     /// The incoming bytes to the decoder.
-    incoming: BitVec,
+    incoming: VecDeque<u8>,
+
+    /// Number of bits of the first byte in `incoming` that have already
+    /// been consumed. Only non-zero after a Synchronization packet that
+    /// left the bitstream misaligned.
+    bit_offset: u32,
 
     /// Whether the decoder is in a state of synchronization.
     sync: Option<usize>,
 
+    /// A decoded header whose payload has yet to be received.
+    stub: Option<PacketStub>,
+
+    /// Number of bytes consumed from the bitstream thus far.
+    consumed: u64,
+
+    /// Where the packet currently being decoded starts in the
+    /// bitstream.
+    packet_start: StreamPosition,
+
+    /// Whether the decoder is recovering from a malformed packet.
+    recovering: bool,
+
+    /// Whether the end of the input has been signalled by
+    /// [Decoder::finish] since data was last pushed.
+    finished: bool,
+
+    /// Number of trailing zero bits in the last byte discarded while
+    /// recovering. Part of the next Synchronization packet, if any.
+    lead_zeros: usize,
+
+    /// Bytes of the packet currently being decoded. Only recorded if
+    /// [DecoderOptions::retain_raw] is set.
+    raw: Vec<u8>,
+
+    /// Number of zeros of the last decoded Synchronization packet, if
+    /// not yet returned with it.
+    raw_sync_zeros: Option<usize>,
+
+    /// The last [MAX_WARNINGS] violations tolerated in
+    /// [ValidationMode::Lenient] since the last [Decoder::take_warnings].
+    warnings: VecDeque<Located<MalformedPacket>>,
+
+    /// Stimulus port page of the last decoded Extension packet.
+    /// (Appendix D4.2.6)
+    stimulus_page: u8,
+
+    /// Counters of the data processed thus far.
+    stats: DecoderStats,
+
     /// Timestamp context. Used exclusively in
     /// [Decoder::pull_with_timestamp] for bookkeeping purposes.
     ts_ctx: TimestampedContext,
 }
 
+/// A copy of the complete state of a [Decoder], including its options
+/// and any data not yet decoded. Serializable via the `serde` feature.
+/// Created by [Decoder::snapshot]; see [Decoder::restore].
+#[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+pub struct DecoderSnapshot(Decoder);
+
 /// Association between a set of [TracePacket]s and their Timestamp.
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(
@@ -522,11 +1235,228 @@ pub struct Decoder {
 pub struct TimestampedTracePackets {
     ///  Timestamp of [packets] and [malformed_packets].
     pub timestamp: Timestamp,
+
+    /// If the local timestamp is delayed relative to the packets
+    /// ([TimestampDataRelation::UnknownDelay] or
+    /// [TimestampDataRelation::UnknownAssocEventDelay]), the timestamp
+    /// of the previous set of packets: the packets were generated
+    /// between `earliest` and `timestamp`. Default if there is no
+    /// previous set in the current clock segment. `None` if `timestamp`
+    /// is exact, or if the previous timestamp has a
+    /// [Timestamp::base] but `timestamp` does not.
+    pub earliest: Option<Timestamp>,
+
     pub packets: Vec<TracePacket>,
-    pub malformed_packets: Vec<MalformedPacket>,
+    pub malformed_packets: Vec<Located<MalformedPacket>>,
+
+    /// Raw data of each of [TimestampedTracePackets::packets], in the
+    /// same order, if [DecoderOptions::retain_raw] is set. Empty
+    /// otherwise. The raw data of malformed packets is part of
+    /// [TimestampedTracePackets::malformed_packets].
+    pub raw: Vec<RawPacket>,
+
+    /// Whether a GlobalTimestamp1 packet with its `clkch` flag set was
+    /// received since the previous set of packets: the input clock to
+    /// the ITM has changed, and `timestamp` is counted from the start
+    /// of a new clock segment. See [WallClock].
+    pub clock_changed: bool,
+
+    /// Whether the packets were returned by
+    /// [Decoder::flush_timestamped] at the end of the input: no local
+    /// timestamp followed them, and `timestamp` is the last known
+    /// timestamp, at or after which they were generated.
+    pub incomplete: bool,
 
     /// Number of ITM packets consumed to create this structure.
     pub packets_consumed: usize,
+
+    /// Where the packets consumed to create this structure are located
+    /// in the bitstream, from the first packet to the timestamp packet.
+    pub location: Location,
+}
+
+/// Formats the packets on a single line, prefixed by their timestamp:
+/// the interval of [TimestampedTracePackets::earliest] and their
+/// timestamp if the former is known, or their timestamp followed by
+/// `..` if [TimestampedTracePackets::incomplete]. A clock change marker
+/// precedes the packets if [TimestampedTracePackets::clock_changed].
+/// The alternate form (`{:#}`) puts each packet on a line of its own,
+/// in its alternate form, after the details of the timestamp.
+impl fmt::Display for TimestampedTracePackets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "[")?;
+            if let Some(earliest) = &self.earliest {
+                write!(f, "{:#} .. ", earliest)?;
+            }
+            write!(f, "{:#}", self.timestamp)?;
+            if self.incomplete {
+                write!(f, " ..")?;
+            }
+            write!(
+                f,
+                "] {} packets at {}",
+                self.packets_consumed, self.location
+            )?;
+            if self.clock_changed {
+                write!(f, "\n  Clock change")?;
+            }
+            for packet in &self.packets {
+                write!(f, "\n  {:#}", packet)?;
+            }
+            for malformed in &self.malformed_packets {
+                write!(f, "\n  Error: {:#}", malformed)?;
+            }
+            return Ok(());
+        }
+
+        write!(f, "[")?;
+        if let Some(earliest) = &self.earliest {
+            write!(f, "{} .. ", earliest)?;
+        }
+        write!(f, "{}", self.timestamp)?;
+        if self.incomplete {
+            write!(f, " ..")?;
+        }
+        write!(f, "]")?;
+        let mut sep = " ";
+        if self.clock_changed {
+            write!(f, "{}Clock change", sep)?;
+            sep = "; ";
+        }
+        for packet in &self.packets {
+            write!(f, "{}{}", sep, packet)?;
+            sep = "; ";
+        }
+        for malformed in &self.malformed_packets {
+            write!(f, "{}Error: {}", sep, malformed)?;
+            sep = "; ";
+        }
+        Ok(())
+    }
+}
+
+/// A position in the bitstream pushed into a [Decoder].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct StreamPosition {
+    /// Number of whole bytes preceding this position.
+    pub byte: u64,
+
+    /// Number of bits of the following byte preceding this position.
+    /// Only non-zero after a Synchronization packet that left the
+    /// bitstream misaligned.
+    pub bit: u8,
+}
+
+impl fmt::Display for StreamPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bit == 0 {
+            write!(f, "byte {}", self.byte)
+        } else {
+            write!(f, "byte {} bit {}", self.byte, self.bit)
+        }
+    }
+}
+
+/// The span of a packet in the bitstream pushed into a [Decoder].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Location {
+    /// Position of the first bit of the packet.
+    pub start: StreamPosition,
+
+    /// Position one past the last bit of the packet.
+    pub end: StreamPosition,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} to {}", self.start, self.end)
+    }
+}
+
+/// A value decoded from the bitstream, and where it was decoded from.
+/// See [Decoder::pull_located].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Located<T> {
+    pub value: T,
+    pub location: Location,
+
+    /// The data `value` was decoded from. Only set if
+    /// [DecoderOptions::retain_raw] is set.
+    pub raw: Option<RawPacket>,
+}
+
+/// The data a packet was decoded from.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct RawPacket {
+    /// Number of zero bits preceding the set bit of a Synchronization
+    /// packet. `None` for all other packets.
+    pub sync_zeros: Option<usize>,
+
+    /// Header and payload bytes of the packet. Empty for
+    /// Synchronization packets.
+    pub bytes: Vec<u8>,
+}
+
+impl<T: fmt::Display> fmt::Display for Located<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "{:#} (at {})", self.value, self.location)
+        } else {
+            write!(f, "{} (at {})", self.value, self.location)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: std::error::Error> std::error::Error for Located<T> {}
+
+/// Iterator over the [TracePacket]s decoded by a [Decoder]. Created by
+/// [Decoder::packets].
+pub struct Packets<'a> {
+    decoder: &'a mut Decoder,
+}
+
+impl Iterator for Packets<'_> {
+    type Item = Result<TracePacket, MalformedPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.decoder.pull().transpose()
+    }
+}
+
+/// Iterator over the [TimestampedTracePackets] decoded by a [Decoder].
+/// Created by [Decoder::timestamped_packets].
+pub struct TimestampedPackets<'a> {
+    decoder: &'a mut Decoder,
+}
+
+impl Iterator for TimestampedPackets<'_> {
+    type Item = TimestampedTracePackets;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.decoder.pull_with_timestamp()
+    }
 }
 
 enum HeaderVariant {
@@ -538,42 +1468,371 @@ impl Decoder {
     pub fn new(options: DecoderOptions) -> Self {
         Decoder {
             options,
-            incoming: BitVec::new(),
+            incoming: VecDeque::new(),
+            bit_offset: 0,
             sync: None,
+            stub: None,
+            consumed: 0,
+            packet_start: StreamPosition::default(),
+            recovering: false,
+            finished: false,
+            lead_zeros: 0,
+            raw: Vec::new(),
+            raw_sync_zeros: None,
+            warnings: VecDeque::new(),
+            stimulus_page: 0,
+            stats: DecoderStats::default(),
             ts_ctx: TimestampedContext::default(),
         }
     }
 
-    /// Push trace data into the decoder.
-    pub fn push(&mut self, data: &[u8]) {
-        // To optimize the performance in pull, we must reverse the
-        // input bitstream and prepend it. This is a costly operation,
-        // but is better done here than elsewhere.
-        let mut bv = BitVec::<LocalBits, _>::from_vec(data.to_vec());
-        bv.reverse();
-        bv.append(&mut self.incoming);
-        self.incoming.append(&mut bv);
+    /// Push trace data into the decoder. Returns the number of bytes
+    /// accepted, which is less than `data.len()` if the input buffer
+    /// reaches [DecoderOptions::capacity]. Returns [BufferFull] if the
+    /// buffer is already full and `data` is not empty.
+    pub fn push(&mut self, data: &[u8]) -> Result<usize, BufferFull> {
+        let len = match self.remaining_capacity() {
+            Some(0) if !data.is_empty() => return Err(BufferFull),
+            Some(remaining) => remaining.min(data.len()),
+            None => data.len(),
+        };
+
+        self.incoming.extend(&data[..len]);
+        if len > 0 {
+            self.finished = false;
+        }
+        Ok(len)
+    }
+
+    /// Signals the end of the input: no more data is pushed until
+    /// decoding resumes with a later [Decoder::push]. Recovery by
+    /// [RecoveryPolicy::Heuristic] then scores the data that remains
+    /// instead of waiting for a full lookahead, so the packets following
+    /// a malformed packet near the end of the input are still decoded.
+    /// Readers, streams and codecs of this crate call this at EOF.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Returns whether [Decoder::finish] has been called since data was
+    /// last pushed.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns the number of bytes buffered in the decoder, including
+    /// any partially decoded packet.
+    pub fn buffered_len(&self) -> usize {
+        self.incoming.len()
+    }
+
+    /// Returns the number of bytes that can be pushed into the decoder
+    /// before its input buffer is full, or `None` if the buffer is
+    /// unbounded.
+    pub fn remaining_capacity(&self) -> Option<usize> {
+        self.options
+            .capacity
+            .map(|capacity| capacity.saturating_sub(self.incoming.len()))
     }
 
     /// Decode the next [TracePacket].
     pub fn pull(&mut self) -> Result<Option<TracePacket>, MalformedPacket> {
-        if self.sync.is_some() {
-            return self.handle_sync();
+        self.pull_located()
+            .map(|packet| packet.map(|p| p.value))
+            .map_err(|malformed| malformed.value)
+    }
+
+    /// As [Decoder::pull], but also returns where the packet, or the
+    /// malformed packet, is located in the bitstream.
+    pub fn pull_located(
+        &mut self,
+    ) -> Result<Option<Located<TracePacket>>, Located<MalformedPacket>> {
+        self.pull_filtered(false)
+    }
+
+    /// As [Decoder::pull_located]. If `timestamps` is set, timestamp and
+    /// Overflow packets are returned whether they match
+    /// [DecoderOptions::filter] or not.
+    fn pull_filtered(
+        &mut self,
+        timestamps: bool,
+    ) -> Result<Option<Located<TracePacket>>, Located<MalformedPacket>> {
+        if self.sync.is_none() && self.stub.is_none() {
+            if self.recovering && !self.recover() {
+                // Need more data to recover
+                return Ok(None);
+            }
+
+            // Next packet starts at the next header, unless recovery
+            // already began a Synchronization packet
+            if self.sync.is_none() {
+                self.packet_start = self.position();
+                self.raw.clear();
+            }
+        }
+
+        match self.decode_next(timestamps) {
+            Ok(Some(value)) => Ok(Some(self.locate(value))),
+            Ok(None) => Ok(None),
+            Err(value) => {
+                self.stats.count_malformed(value.kind());
+                self.recovering = self.options.recovery != RecoveryPolicy::Naive;
+                Err(self.locate(value))
+            }
         }
-        assert!(self.sync.is_none());
+    }
 
-        if self.incoming.len() < 8 {
-            // No header to decode, nothing to do
-            return Ok(None);
+    /// Associates a just decoded value with its location in the
+    /// bitstream, and its raw data if retained.
+    fn locate<T>(&mut self, value: T) -> Located<T> {
+        let raw = if self.options.retain_raw {
+            Some(match self.raw_sync_zeros.take() {
+                Some(zeros) => RawPacket {
+                    sync_zeros: Some(zeros),
+                    bytes: vec![],
+                },
+                None => RawPacket {
+                    sync_zeros: None,
+                    bytes: core::mem::take(&mut self.raw),
+                },
+            })
+        } else {
+            None
+        };
+
+        Located {
+            value,
+            location: Location {
+                start: self.packet_start,
+                end: self.position(),
+            },
+            raw,
         }
+    }
+
+    /// Returns a snapshot of the complete state of the decoder: its
+    /// options, the data pushed but not yet decoded, any partially
+    /// decoded packet, and the timestamp context of
+    /// [Decoder::pull_with_timestamp].
+    pub fn snapshot(&self) -> DecoderSnapshot {
+        DecoderSnapshot(self.clone())
+    }
+
+    /// Creates a decoder from a snapshot. Decoding resumes exactly where
+    /// the decoder the snapshot was taken of left off.
+    pub fn restore(snapshot: DecoderSnapshot) -> Self {
+        snapshot.0
+    }
+
+    /// Returns the number of bytes discarded while recovering from
+    /// malformed packets since the decoder was created or
+    /// [Decoder::reset_stats] was last called. Shorthand for
+    /// [DecoderStats::bytes_discarded]. See [RecoveryPolicy].
+    pub fn bytes_discarded(&self) -> u64 {
+        self.stats.bytes_discarded
+    }
+
+    /// Returns the reserved-bit and reserved-encoding violations of the
+    /// packets decoded in [ValidationMode::Lenient] since the last call,
+    /// located at the packets they were found in. Only the 32 most
+    /// recent violations are retained; all are counted in
+    /// [DecoderStats::warnings].
+    pub fn take_warnings(&mut self) -> Vec<Located<MalformedPacket>> {
+        self.warnings.drain(..).collect()
+    }
+
+    /// Returns the counters of the data processed since the decoder
+    /// was created or [Decoder::reset_stats] was last called.
+    pub fn stats(&self) -> &DecoderStats {
+        &self.stats
+    }
+
+    /// Resets all counters of [Decoder::stats] to zero.
+    pub fn reset_stats(&mut self) {
+        self.stats = DecoderStats::default();
+    }
 
-        self.ts_ctx.packets_consumed += 1;
-        match Self::decode_header(self.pull_byte())? {
-            HeaderVariant::Packet(p) => Ok(Some(p)),
-            HeaderVariant::Stub(s) => self.process_stub(&s),
+    /// Returns the position in the bitstream of the next bit to be
+    /// decoded.
+    pub fn position(&self) -> StreamPosition {
+        StreamPosition {
+            byte: self.consumed,
+            bit: self.bit_offset as u8,
         }
     }
 
+    /// Decodes the next packet that matches [DecoderOptions::filter],
+    /// or that is a timestamp or Overflow packet if `timestamps` is set.
+    /// Other packets are consumed without being returned; the payloads
+    /// of source and timestamp packets are skipped without being
+    /// decoded.
+    fn decode_next(&mut self, timestamps: bool) -> Result<Option<TracePacket>, MalformedPacket> {
+        loop {
+            if self.sync.is_some() {
+                match self.handle_sync()? {
+                    Some(p) if !self.accept(p.kind(), timestamps) => continue,
+                    res => return Ok(res),
+                }
+            }
+
+            let stub = if let Some(stub) = self.stub.take() {
+                // Header already decoded; try again to pull its payload
+                stub
+            } else {
+                let header = if let Some(header) = self.pull_byte() {
+                    header
+                } else {
+                    // No header to decode, nothing to do
+                    return Ok(None);
+                };
+
+                self.ts_ctx.packets_consumed += 1;
+                match Self::decode_header(header)? {
+                    HeaderVariant::Packet(p) => {
+                        if let TracePacket::Extension { page } = p {
+                            self.stimulus_page = page;
+                        }
+                        if self.accept(p.kind(), timestamps) {
+                            return Ok(Some(p));
+                        }
+                        continue;
+                    }
+                    HeaderVariant::Stub(s) if self.stub_matches(&s, timestamps) => s,
+                    HeaderVariant::Stub(s) => PacketStub::Skip {
+                        kind: Self::stub_kind(&s),
+                        expected_size: match s {
+                            PacketStub::Instrumentation { expected_size, .. }
+                            | PacketStub::HardwareSource { expected_size, .. } => {
+                                Some(expected_size)
+                            }
+                            _ => None,
+                        },
+                    },
+                }
+            };
+
+            if let PacketStub::Skip {
+                kind,
+                expected_size,
+            } = stub
+            {
+                if !self.skip_payload(kind, expected_size) {
+                    self.stub = Some(stub);
+                    return Ok(None);
+                }
+                self.stats.count_packet(kind);
+                self.skip_packet();
+                continue;
+            }
+
+            match self.process_stub(stub)? {
+                Some(p) if !self.accept(p.kind(), timestamps) => (),
+                res => return Ok(res),
+            }
+        }
+    }
+
+    /// Counts a decoded packet of the given kind, and returns whether
+    /// it matches [DecoderOptions::filter], or is a timestamp or
+    /// Overflow packet if `timestamps` is set. If not, the packet is
+    /// discarded.
+    fn accept(&mut self, kind: PacketKind, timestamps: bool) -> bool {
+        self.stats.count_packet(kind);
+        if self.options.filter.matches_kind(kind) || (timestamps && kind.is_timing()) {
+            true
+        } else {
+            self.skip_packet();
+            false
+        }
+    }
+
+    /// Whether the packet of a decoded header matches
+    /// [DecoderOptions::filter], or is a timestamp packet if
+    /// `timestamps` is set. Synchronization packets are matched once
+    /// decoded.
+    fn stub_matches(&self, stub: &PacketStub, timestamps: bool) -> bool {
+        match stub {
+            PacketStub::Sync(_) => true,
+            stub if timestamps && Self::stub_kind(stub).is_timing() => true,
+            PacketStub::Instrumentation { port, .. } => self
+                .options
+                .filter
+                .matches_port(self.stimulus_page * 32 + port),
+            stub => self.options.filter.matches_kind(Self::stub_kind(stub)),
+        }
+    }
+
+    /// Returns the kind of the packet of a decoded header.
+    fn stub_kind(stub: &PacketStub) -> PacketKind {
+        match stub {
+            PacketStub::Sync(_) => PacketKind::Sync,
+            PacketStub::Instrumentation { .. } => PacketKind::Instrumentation,
+            PacketStub::HardwareSource { disc_id, .. } => match disc_id {
+                0 => PacketKind::EventCounterWrap,
+                1 => PacketKind::ExceptionTrace,
+                2 => PacketKind::PCSample,
+                // data trace; see Decoder::handle_hardware_source
+                _ => match (disc_id >> 3, disc_id & 1) {
+                    (0b01, 0) => PacketKind::DataTracePC,
+                    (0b01, _) => PacketKind::DataTraceAddress,
+                    _ => PacketKind::DataTraceValue,
+                },
+            },
+            PacketStub::LocalTimestamp { .. } => PacketKind::LocalTimestamp1,
+            PacketStub::GlobalTimestamp1 => PacketKind::GlobalTimestamp1,
+            PacketStub::GlobalTimestamp2 => PacketKind::GlobalTimestamp2,
+            PacketStub::Skip { kind, .. } => *kind,
+        }
+    }
+
+    /// Discards a packet that does not match [DecoderOptions::filter]:
+    /// the next packet starts at the current position.
+    fn skip_packet(&mut self) {
+        self.packet_start = self.position();
+        self.raw.clear();
+        self.raw_sync_zeros = None;
+    }
+
+    /// Discards the payload of a packet of the given kind that does
+    /// not match [DecoderOptions::filter]: `expected_size` bytes, or
+    /// until the continuation-bit is not set if `None`, but no more
+    /// than the maximum payload size of a timestamp packet. Returns
+    /// `false` if the payload has not been fully received yet.
+    fn skip_payload(&mut self, kind: PacketKind, expected_size: Option<usize>) -> bool {
+        let cnt = match expected_size {
+            Some(cnt) if self.bytes_available() >= cnt => cnt,
+            Some(_) => return false,
+            None => {
+                let max = match kind {
+                    PacketKind::GlobalTimestamp2 => MAX_GTS2_PAYLOAD,
+                    _ => MAX_TS_PAYLOAD,
+                };
+                match self.continued_len(max) {
+                    Some(cnt) => cnt,
+                    None => return false,
+                }
+            }
+        };
+
+        for _ in 0..cnt {
+            self.pull_byte();
+        }
+        true
+    }
+
+    /// Returns an iterator that [Decoder::pull]s packets until no more
+    /// packets can be decoded from the data pushed thus far.
+    pub fn packets(&mut self) -> Packets<'_> {
+        Packets { decoder: self }
+    }
+
+    /// Returns an iterator that [Decoder::pull_with_timestamp]s sets of
+    /// packets until no more sets can be decoded from the data pushed
+    /// thus far.
+    pub fn timestamped_packets(&mut self) -> TimestampedPackets<'_> {
+        TimestampedPackets { decoder: self }
+    }
+
     /// Pull the next set of ITM data packets (not timestamps) from the
     /// decoder and associates a [Timestamp]. **Assumes that local
     /// timestamps will be found in the bitstream.**
@@ -591,101 +1850,153 @@ impl Decoder {
     pub fn pull_with_timestamp(&mut self) -> Option<TimestampedTracePackets> {
         // Common functionality for LTS{1,2}
         fn assoc_packets_with_lts(
-            packets: Vec<TracePacket>,
-            malformed_packets: Vec<MalformedPacket>,
-            ts: &mut Timestamp,
+            ctx: &mut TimestampedContext,
             lts: usize,
             data_relation: TimestampDataRelation,
-            packets_consumed: &mut usize,
+            location: Location,
         ) -> TimestampedTracePackets {
+            let ts = &mut ctx.ts;
             if let Some(ref mut delta) = ts.delta {
-                *delta += lts as usize;
+                // Saturates on targets where usize is 32 bits wide
+                *delta = delta.saturating_add(lts);
             } else {
                 ts.delta = Some(lts);
             }
+
+            // The packets may have been generated as early as the last
+            // local timestamp. (Appendix D4.2.4)
+            // The previous timestamp cannot be compared to this one
+            // if only the previous one has a base.
+            let earliest = match data_relation {
+                TimestampDataRelation::UnknownDelay
+                | TimestampDataRelation::UnknownAssocEventDelay
+                    if ctx.previous.base.is_none() || ts.base.is_some() =>
+                {
+                    Some(ctx.previous.clone())
+                }
+                _ => None,
+            };
             ts.data_relation = Some(data_relation);
+            ctx.previous = ts.clone();
+
             let ttp = TimestampedTracePackets {
                 timestamp: ts.clone(),
-                packets,
-                malformed_packets,
-                packets_consumed: *packets_consumed,
+                earliest,
+                packets: ctx.packets.drain(..).collect(),
+                malformed_packets: ctx.malformed_packets.drain(..).collect(),
+                raw: ctx.raw.drain(..).collect(),
+                clock_changed: core::mem::take(&mut ctx.clock_changed),
+                incomplete: false,
+                packets_consumed: ctx.packets_consumed,
+                location,
             };
-            *packets_consumed = 0;
+            ctx.packets_consumed = 0;
             ttp
         }
 
         loop {
-            match self.pull() {
+            // Timestamp packets are needed whether they match the
+            // filter or not
+            let (packet, location, raw) = match self.pull_filtered(true) {
                 // No packets remaining
                 Ok(None) => return None,
 
+                Ok(Some(Located {
+                    value,
+                    location,
+                    raw,
+                })) => (Ok(value), location, raw),
+                Err(malformed) => {
+                    let location = malformed.location;
+                    (Err(malformed), location, None)
+                }
+            };
+            let span = Location {
+                start: *self.ts_ctx.start.get_or_insert(location.start),
+                end: location.end,
+            };
+            self.ts_ctx.end = location.end;
+
+            match packet {
                 // A local timestamp: packets received after the last
                 // local timestamp (all self.ts_ctx.packets) relate to
                 // this local timestamp. Return the packets and
                 // timestamp.
-                Ok(Some(TracePacket::LocalTimestamp1 { ts, data_relation }))
+                Ok(TracePacket::LocalTimestamp1 { ts, data_relation })
                     if !self.options.only_gts =>
                 {
+                    self.ts_ctx.start = None;
                     return Some(assoc_packets_with_lts(
-                        self.ts_ctx.packets.drain(..).collect(),
-                        self.ts_ctx.malformed_packets.drain(..).collect(),
-                        &mut self.ts_ctx.ts,
-                        ts as usize,
+                        &mut self.ts_ctx,
+                        (ts as usize).saturating_mul(self.options.lts_prescaler.divisor()),
                         data_relation,
-                        &mut self.ts_ctx.packets_consumed,
+                        span,
                     ));
                 }
-                Ok(Some(TracePacket::LocalTimestamp2 { ts })) if !self.options.only_gts => {
+                Ok(TracePacket::LocalTimestamp2 { ts }) if !self.options.only_gts => {
+                    self.ts_ctx.start = None;
                     return Some(assoc_packets_with_lts(
-                        self.ts_ctx.packets.drain(..).collect(),
-                        self.ts_ctx.malformed_packets.drain(..).collect(),
-                        &mut self.ts_ctx.ts,
-                        ts as usize,
+                        &mut self.ts_ctx,
+                        (ts as usize).saturating_mul(self.options.lts_prescaler.divisor()),
                         TimestampDataRelation::Sync,
-                        &mut self.ts_ctx.packets_consumed,
+                        span,
                     ));
                 }
 
                 // A global timestamp: store until we have both the
                 // upper (GTS2) and lower bits (GTS1).
-                Ok(Some(TracePacket::GlobalTimestamp1 { ts, wrap, clkch })) => {
+                Ok(TracePacket::GlobalTimestamp1 { ts, wrap, clkch }) => {
                     self.ts_ctx.gts1 = Some(ts as usize);
                     if wrap {
                         // upper bits have changed; GTS2 incoming
                         self.ts_ctx.gts2 = None;
                     }
                     if clkch {
-                        // changed input clock to ITM; full GTS incoming
-                        self.ts_ctx.gts1 = None;
+                        // changed input clock to ITM; GTS2 incoming.
+                        // Timestamps of the new clock segment do not
+                        // follow from those before it.
                         self.ts_ctx.gts2 = None;
+                        self.ts_ctx.ts = Timestamp::default();
+                        self.ts_ctx.previous = Timestamp::default();
+                        self.ts_ctx.clock_changed = true;
                     }
                 }
-                Ok(Some(TracePacket::GlobalTimestamp2 { ts })) => {
-                    self.ts_ctx.gts2 = Some(ts as usize)
-                }
+                Ok(TracePacket::GlobalTimestamp2 { ts }) => self.ts_ctx.gts2 = Some(ts as usize),
 
                 // An overflow: the local timestamp may potentially have
                 // wrapped around, but this is not necessarily the case.
                 // We can in any case no longer generate an accurate
                 // Timestamp.
-                Ok(Some(TracePacket::Overflow)) => {
+                Ok(TracePacket::Overflow) => {
                     self.ts_ctx.ts.diverged = true;
-                    self.ts_ctx.packets.push(TracePacket::Overflow);
+                    if self.options.filter.matches_kind(PacketKind::Overflow) {
+                        self.ts_ctx.push(TracePacket::Overflow, raw);
+                    }
                 }
 
+                // A local timestamp considered data (see below) that
+                // does not match the filter.
+                Ok(packet) if !self.options.filter.matches_kind(packet.kind()) => (),
+
                 // A packet that doesn't relate to the timestamp: stash
                 // it until the next local timestamp.
-                Ok(Some(packet)) if !self.options.only_gts => self.ts_ctx.packets.push(packet),
+                Ok(packet) if !self.options.only_gts => self.ts_ctx.push(packet, raw),
 
                 Err(malformed) => self.ts_ctx.malformed_packets.push(malformed),
 
                 // As above, but with local timestamps considered data: return the packet directly.
-                Ok(Some(packet)) if self.options.only_gts => {
+                Ok(packet) if self.options.only_gts => {
+                    self.ts_ctx.start = None;
                     return Some(TimestampedTracePackets {
                         timestamp: self.ts_ctx.ts.clone(),
+                        earliest: None,
                         packets: vec![packet],
                         malformed_packets: vec![],
+                        raw: raw.into_iter().collect(),
+                        clock_changed: core::mem::take(&mut self.ts_ctx.clock_changed),
+                        incomplete: false,
                         packets_consumed: 1,
+                        location,
                     });
                 }
                 _ => unreachable!(),
@@ -703,79 +2014,310 @@ impl Decoder {
         }
     }
 
+    /// Returns the packets and malformed packets
+    /// [Decoder::pull_with_timestamp] has consumed since the last local
+    /// timestamp, which would otherwise only be returned along with the
+    /// next one. Intended for the end of the input, once
+    /// [Decoder::finish] has been called and
+    /// [Decoder::pull_with_timestamp] returns `None`: the set is
+    /// associated with the last known timestamp and marked
+    /// [TimestampedTracePackets::incomplete]. Returns `None` if no such
+    /// packets remain.
+    ///
+    /// Data of a packet that is only partially pushed remains buffered.
+    pub fn flush_timestamped(&mut self) -> Option<TimestampedTracePackets> {
+        let ctx = &mut self.ts_ctx;
+        if ctx.packets.is_empty() && ctx.malformed_packets.is_empty() {
+            return None;
+        }
+
+        // No local timestamp relates to the packets
+        let mut timestamp = ctx.ts.clone();
+        timestamp.data_relation = None;
+
+        Some(TimestampedTracePackets {
+            timestamp,
+            earliest: None,
+            packets: ctx.packets.drain(..).collect(),
+            malformed_packets: ctx.malformed_packets.drain(..).collect(),
+            raw: ctx.raw.drain(..).collect(),
+            clock_changed: core::mem::take(&mut ctx.clock_changed),
+            incomplete: true,
+            packets_consumed: core::mem::take(&mut ctx.packets_consumed),
+            location: Location {
+                start: ctx.start.take().unwrap_or(ctx.end),
+                end: ctx.end,
+            },
+        })
+    }
+
+    /// Discards data according to [DecoderOptions::recovery]. Returns
+    /// `false` if more data is required to finish recovering.
+    fn recover(&mut self) -> bool {
+        match self.options.recovery {
+            RecoveryPolicy::Naive => (),
+            RecoveryPolicy::SkipToSync => loop {
+                match self.peek_byte(0) {
+                    None => return false,
+                    Some(0) => {
+                        // Zero bits preceding this byte in the
+                        // bitstream are part of the Synchronization
+                        // packet.
+                        self.packet_start = self.position();
+                        self.pull_byte();
+                        self.ts_ctx.packets_consumed += 1;
+                        self.sync = Some(8 + self.lead_zeros);
+                        break;
+                    }
+                    Some(b) => {
+                        // Bits are consumed from the LSB: the MSB zeros
+                        // directly precede the next byte.
+                        self.lead_zeros = b.leading_zeros() as usize;
+                        self.pull_byte();
+                        self.stats.bytes_discarded += 1;
+                    }
+                }
+            },
+            RecoveryPolicy::Heuristic => {
+                // At the end of the input, make do with what remains
+                let available = self.bytes_available();
+                if available == 0 || (available < RESYNC_LOOKAHEAD && !self.finished) {
+                    return false;
+                }
+
+                let lookahead: Vec<u8> = (0..available.min(RESYNC_LOOKAHEAD))
+                    .map(|i| self.peek_byte(i).unwrap())
+                    .collect();
+                let mut best = (0, 0);
+                for offset in 0..RESYNC_WINDOW.min(lookahead.len()) {
+                    let score =
+                        Self::score_alignment(&lookahead[offset..], self.options.validation);
+                    if score > best.1 {
+                        best = (offset, score);
+                    }
+                }
+
+                for _ in 0..best.0 {
+                    self.pull_byte();
+                }
+                self.stats.bytes_discarded += best.0 as u64;
+            }
+        }
+
+        self.recovering = false;
+        self.lead_zeros = 0;
+        true
+    }
+
+    /// Returns the number of consecutive packets, up to
+    /// [RESYNC_PACKETS], that successfully decode from `bytes`. A packet
+    /// truncated by the end of `bytes` is considered valid.
+    fn score_alignment(mut bytes: &[u8], validation: ValidationMode) -> usize {
+        let mut score = 0;
+        while score < RESYNC_PACKETS {
+            let (header, rest) = match bytes.split_first() {
+                Some((header, rest)) => (*header, rest),
+                None => break,
+            };
+
+            // Length of the continuation-bit payload, if it is at most
+            // `max` bytes long
+            let continued = |max: usize| match rest.iter().position(|b| b & (1 << 7) == 0) {
+                Some(i) if i < max => Some(i + 1),
+                Some(_) => None,
+                None => Some(rest.len().min(max)),
+            };
+
+            let len = match Self::decode_header(header) {
+                Err(_) => break,
+                Ok(HeaderVariant::Packet(_)) => Some(0),
+                Ok(HeaderVariant::Stub(stub)) => match stub {
+                    // Assume the zeros are followed by a valid Synchronization packet
+                    PacketStub::Sync(_) => return score + 1,
+                    PacketStub::Instrumentation { expected_size, .. } => Some(expected_size),
+                    PacketStub::HardwareSource {
+                        disc_id,
+                        expected_size,
+                    } => match rest.get(..expected_size) {
+                        Some(payload) => {
+                            let payload = Payload::from_slice(payload).unwrap();
+                            let reserved = validation == ValidationMode::Strict
+                                && Self::check_reserved(disc_id, &payload).is_some();
+                            if reserved || Self::handle_hardware_source(disc_id, payload).is_err() {
+                                None
+                            } else {
+                                Some(expected_size)
+                            }
+                        }
+                        None => Some(expected_size),
+                    },
+                    PacketStub::LocalTimestamp { .. } | PacketStub::GlobalTimestamp1 => {
+                        continued(MAX_TS_PAYLOAD)
+                    }
+                    PacketStub::GlobalTimestamp2 => match continued(MAX_GTS2_PAYLOAD) {
+                        Some(len) if len == 4 || len == MAX_GTS2_PAYLOAD || len == rest.len() => {
+                            Some(len)
+                        }
+                        _ => None,
+                    },
+                    PacketStub::Skip { .. } => unreachable!(), // not decoded from a header
+                },
+            };
+
+            match len {
+                None => break,
+                Some(len) if len > rest.len() => return score + 1,
+                Some(len) => {
+                    bytes = &rest[len..];
+                    score += 1;
+                }
+            }
+        }
+
+        score
+    }
+
     /// Read zeros from the bitstream until the first bit is set. This
     /// realigns the incoming bitstream for further processing, which
-    /// may not be 8-bit aligned.
+    /// may not be 8-bit aligned. A Synchronization packet is at least 47
+    /// zeros followed by a set bit. (Appendix D4.2.1)
     fn handle_sync(&mut self) -> Result<Option<TracePacket>, MalformedPacket> {
         if let Some(mut count) = self.sync {
-            while let Some(bit) = self.incoming.pop() {
-                if !bit && count < SYNC_MIN_ZEROS {
+            while let Some(bit) = self.pull_bit() {
+                if !bit {
                     count += 1;
                     continue;
-                } else if bit && count >= SYNC_MIN_ZEROS {
+                } else if count >= self.options.sync_min_zeros {
                     self.sync = None;
+                    self.raw_sync_zeros = Some(count);
                     return Ok(Some(TracePacket::Sync));
                 } else {
                     self.sync = None;
+                    self.raw_sync_zeros = Some(count);
                     return Err(MalformedPacket::InvalidSync(count));
                 }
             }
+
+            // Bitstream exhausted: remember the zeros read thus far
+            self.sync = Some(count);
         }
 
         Ok(None)
     }
 
-    /// Pulls a single byte from the incoming buffer.
-    fn pull_byte(&mut self) -> u8 {
-        let mut b: u8 = 0;
-        for i in 0..8 {
-            b |= (self.incoming.pop().unwrap() as u8) << i;
+    /// Pulls a single bit from the incoming buffer. Bits are consumed
+    /// from the least significant bit of each byte.
+    fn pull_bit(&mut self) -> Option<bool> {
+        let bit = (self.incoming.front()? >> self.bit_offset) & 1 == 1;
+        self.bit_offset += 1;
+        if self.bit_offset == 8 {
+            self.incoming.pop_front();
+            self.consumed += 1;
+            self.stats.bytes_consumed += 1;
+            self.bit_offset = 0;
         }
 
-        b
+        Some(bit)
+    }
+
+    /// Number of whole bytes available in the incoming buffer.
+    fn bytes_available(&self) -> usize {
+        if self.bit_offset == 0 {
+            self.incoming.len()
+        } else {
+            self.incoming.len().saturating_sub(1)
+        }
+    }
+
+    /// Returns the `i`th byte in the incoming buffer without consuming
+    /// it, if available.
+    fn peek_byte(&self, i: usize) -> Option<u8> {
+        if self.bit_offset == 0 {
+            self.incoming.get(i).copied()
+        } else {
+            // Bitstream is misaligned: construct the byte from the
+            // upper bits of one byte and the lower bits of the next.
+            let lo = self.incoming.get(i)? >> self.bit_offset;
+            let hi = self.incoming.get(i + 1)? << (8 - self.bit_offset);
+            Some(lo | hi)
+        }
+    }
+
+    /// Pulls a single byte from the incoming buffer, if available.
+    fn pull_byte(&mut self) -> Option<u8> {
+        let b = self.peek_byte(0)?;
+        self.incoming.pop_front();
+        self.consumed += 1;
+        self.stats.bytes_consumed += 1;
+        if self.options.retain_raw {
+            self.raw.push(b);
+        }
+        Some(b)
     }
 
     /// Pulls `cnt` bytes from the incoming buffer, if `cnt` bytes are
-    /// available.
-    fn pull_bytes(&mut self, cnt: usize) -> Option<Vec<u8>> {
-        if self.incoming.len() < cnt * 8 {
+    /// available. `cnt` must not exceed the capacity of the payload.
+    fn pull_bytes<const N: usize>(&mut self, cnt: usize) -> Option<Payload<N>> {
+        debug_assert!(cnt <= N);
+        if self.bytes_available() < cnt {
             return None;
         }
 
-        let mut payload = vec![];
+        let mut payload = Payload::new();
         for _ in 0..cnt {
-            payload.push(self.pull_byte());
+            payload.push(self.pull_byte().unwrap());
         }
         Some(payload)
     }
 
     /// Pulls bytes from the incoming buffer until the continuation-bit
-    /// is not set. All [PacketStub]s follow follow this payload schema.
-    /// (e.g. Appendix D4, Fig. D4-4)
-    fn pull_payload(&mut self) -> Option<Vec<u8>> {
-        let mut iter = self.incoming.rchunks(8);
+    /// is not set, or `max` bytes have been pulled. All [PacketStub]s
+    /// follow follow this payload schema. (e.g. Appendix D4, Fig. D4-4)
+    fn pull_payload(&mut self, max: usize) -> Option<Vec<u8>> {
+        let cnt = self.continued_len(max)?;
+        (0..cnt).map(|_| self.pull_byte()).collect()
+    }
+
+    /// Returns the length of the continuation-bit payload at the start
+    /// of the incoming buffer, or `max` if it is longer. Returns `None`
+    /// if the payload has not been fully received yet.
+    fn continued_len(&self, max: usize) -> Option<usize> {
         let mut cnt = 0;
         loop {
+            let b = self.peek_byte(cnt)?;
             cnt += 1;
-            match iter.next() {
-                None => return None,
-                Some(b) if b.len() < 8 => return None,
-                Some(b) => match b.first_zero() {
-                    // bit 7 is not set: we have reached the end of the
-                    // payload
-                    //
-                    // TODO replace with Option::contains when stable
-                    Some(0) => break,
-                    _ => continue,
-                },
+
+            // bit 7 is not set: we have reached the end of the payload
+            if b & (1 << 7) == 0 || cnt == max {
+                return Some(cnt);
             }
         }
+    }
 
-        Some(self.pull_bytes(cnt).unwrap())
+    /// Whether a continuation-bit payload does not end within the bytes
+    /// pulled by [Decoder::pull_payload].
+    fn is_continued(payload: &[u8]) -> bool {
+        payload.last().is_some_and(|b| b & (1 << 7) != 0)
     }
 
-    fn process_stub(&mut self, stub: &PacketStub) -> Result<Option<TracePacket>, MalformedPacket> {
+    /// Attempts to pull the payload of `stub`. If the payload has not
+    /// been fully received yet, the stub is kept until the next
+    /// [Decoder::pull].
+    fn process_stub(&mut self, stub: PacketStub) -> Result<Option<TracePacket>, MalformedPacket> {
+        let res = self.decode_stub(&stub);
+
+        // An incomplete Synchronization packet is tracked by `self.sync`
+        if matches!(res, Ok(None)) && self.sync.is_none() {
+            self.stub = Some(stub);
+        }
+
+        res
+    }
+
+    fn decode_stub(&mut self, stub: &PacketStub) -> Result<Option<TracePacket>, MalformedPacket> {
         match stub {
+            PacketStub::Skip { .. } => unreachable!(), // handled by Decoder::decode_next
+
             PacketStub::Sync(count) => {
                 self.sync = Some(*count);
                 self.handle_sync()
@@ -786,13 +2328,49 @@ impl Decoder {
                 expected_size,
             } => {
                 if let Some(payload) = self.pull_bytes(*expected_size) {
-                    Self::handle_hardware_source(*disc_id, payload).map(Some)
+                    if let Some(violation) = Self::check_reserved(*disc_id, &payload) {
+                        match self.options.validation {
+                            ValidationMode::Strict => return Err(violation),
+                            ValidationMode::Lenient => {
+                                self.stats.warnings += 1;
+                                if self.warnings.len() == MAX_WARNINGS {
+                                    self.warnings.pop_front();
+                                }
+                                self.warnings.push_back(Located {
+                                    value: violation,
+                                    location: Location {
+                                        start: self.packet_start,
+                                        end: self.position(),
+                                    },
+                                    raw: None,
+                                });
+                            }
+                        }
+                    }
+
+                    match Self::handle_hardware_source(*disc_id, payload)? {
+                        TracePacket::DataTracePC { comparator, .. }
+                        | TracePacket::DataTraceAddress { comparator, .. }
+                        | TracePacket::DataTraceValue { comparator, .. }
+                            if comparator >= self.options.comparators =>
+                        {
+                            Err(MalformedPacket::InvalidComparator(comparator))
+                        }
+                        packet => Ok(Some(packet)),
+                    }
                 } else {
                     Ok(None)
                 }
             }
             PacketStub::LocalTimestamp { data_relation } => {
-                if let Some(payload) = self.pull_payload() {
+                if let Some(payload) = self.pull_payload(MAX_TS_PAYLOAD) {
+                    if Self::is_continued(&payload) {
+                        return Err(MalformedPacket::InvalidTimestampSize {
+                            kind: PacketKind::LocalTimestamp1,
+                            payload: Payload::from_slice(&payload).unwrap(),
+                        });
+                    }
+
                     Ok(Some(TracePacket::LocalTimestamp1 {
                         data_relation: data_relation.clone(),
                         ts: Decoder::extract_timestamp(payload, 27),
@@ -802,7 +2380,14 @@ impl Decoder {
                 }
             }
             PacketStub::GlobalTimestamp1 => {
-                if let Some(payload) = self.pull_payload() {
+                if let Some(payload) = self.pull_payload(MAX_TS_PAYLOAD) {
+                    if Self::is_continued(&payload) {
+                        return Err(MalformedPacket::InvalidTimestampSize {
+                            kind: PacketKind::GlobalTimestamp1,
+                            payload: Payload::from_slice(&payload).unwrap(),
+                        });
+                    }
+
                     Ok(Some(TracePacket::GlobalTimestamp1 {
                         ts: Decoder::extract_timestamp(payload.clone(), 25),
                         clkch: (payload.last().unwrap() & (1 << 5)) >> 5 == 1,
@@ -813,16 +2398,27 @@ impl Decoder {
                 }
             }
             PacketStub::GlobalTimestamp2 => {
-                if let Some(payload) = self.pull_payload() {
+                if let Some(payload) = self.pull_payload(MAX_GTS2_PAYLOAD) {
                     Ok(Some(TracePacket::GlobalTimestamp2 {
                         ts: Decoder::extract_timestamp(
                             payload.to_vec(),
-                            match payload.len() {
-                                4 => 47 - 26, // 48 bit timestamp
-                                6 => 63 - 26, // 64 bit timestamp
+                            match (payload.len(), self.options.gts2_width) {
+                                // Longer than a 64 bit timestamp
+                                _ if Self::is_continued(&payload) => {
+                                    return Err(MalformedPacket::InvalidGTS2Size {
+                                        payload: Payload::from_slice(&payload).unwrap(),
+                                    })
+                                }
+                                // 48 bit timestamp
+                                (4, None | Some(GlobalTimestampWidth::Bits48)) => 47 - 26,
+                                // 64 bit timestamp
+                                (6, None | Some(GlobalTimestampWidth::Bits64)) => 63 - 26,
                                 _ => {
                                     return Err(MalformedPacket::InvalidGTS2Size {
-                                        payload: payload.to_vec(),
+                                        payload: Payload::from_slice(
+                                            &payload[..payload.len().min(7)],
+                                        )
+                                        .unwrap(),
                                     })
                                 }
                             },
@@ -837,9 +2433,18 @@ impl Decoder {
                 expected_size,
             } => {
                 if let Some(payload) = self.pull_bytes(*expected_size) {
+                    let full_port = self.stimulus_page * 32 + port;
+                    if u16::from(full_port) >= self.options.stimulus_ports {
+                        return Err(MalformedPacket::InvalidStimulusPort(full_port.into()));
+                    }
+
                     Ok(Some(TracePacket::Instrumentation {
-                        port: *port,
-                        payload: payload.to_vec(),
+                        port: if self.options.raw_ports {
+                            *port
+                        } else {
+                            full_port
+                        },
+                        payload,
                     }))
                 } else {
                     Ok(None)
@@ -865,11 +2470,45 @@ impl Decoder {
         ts | (((head[0] & mask) as u64) << (7 * rtail.len()))
     }
 
-    /// Decodes the payload of a hardware source packet.
+    /// Returns the violation if the hardware source packet sets reserved
+    /// bits or uses a reserved encoding.
+    #[bitmatch]
+    fn check_reserved(disc_id: u8, payload: &Payload) -> Option<MalformedPacket> {
+        let reserved_bits = match disc_id {
+            0 => payload.first().is_some_and(|b| b & 0b1100_0000 != 0),
+            1 => payload.get(1).is_some_and(|b| b & 0b1100_1110 != 0),
+            2 => payload.len() == 1 && payload[0] != 0,
+            8..=23 => {
+                #[bitmatch]
+                let "???t_t??d" = disc_id;
+                return match (t, d, payload.len()) {
+                    (0b01, 0, 4) | (0b01, 1, 2) | (0b10, _, _) => None,
+                    _ => Some(MalformedPacket::ReservedEncoding {
+                        disc_id,
+                        payload: *payload,
+                    }),
+                };
+            }
+            _ => false,
+        };
+
+        if reserved_bits {
+            Some(MalformedPacket::ReservedBits {
+                disc_id,
+                payload: *payload,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Decodes the payload of a hardware source packet. Reserved bits
+    /// are ignored and data trace packets of reserved payload sizes are
+    /// zero-extended or truncated; see [Decoder::check_reserved].
     #[bitmatch]
     fn handle_hardware_source(
         disc_id: u8,
-        payload: Vec<u8>,
+        payload: Payload,
     ) -> Result<TracePacket, MalformedPacket> {
         match disc_id {
             0 => {
@@ -898,32 +2537,17 @@ impl Decoder {
 
                 let function = (payload[1] >> 4) & 0b11;
                 let exception_number = ((payload[1] as u16 & 1) << 8) | payload[0] as u16;
-                let exception_number: u8 = if let Ok(nr) = exception_number.try_into() {
-                    nr
-                } else {
-                    return Err(MalformedPacket::InvalidExceptionTrace {
-                        exception: exception_number,
-                        function,
-                    });
-                };
 
                 Ok(TracePacket::ExceptionTrace {
-                    exception: if let Some(exception) = cortex_m::VectActive::from(exception_number)
-                    {
-                        exception
-                    } else {
-                        return Err(MalformedPacket::InvalidExceptionTrace {
-                            exception: exception_number.into(),
-                            function,
-                        });
-                    },
+                    // A 9-bit exception number is always valid
+                    exception: ExceptionType::from_number(exception_number).unwrap(),
                     action: match function {
                         0b01 => ExceptionAction::Entered,
                         0b10 => ExceptionAction::Exited,
                         0b11 => ExceptionAction::Returned,
                         _ => {
                             return Err(MalformedPacket::InvalidExceptionTrace {
-                                exception: exception_number.into(),
+                                exception: exception_number,
                                 function,
                             })
                         }
@@ -933,9 +2557,9 @@ impl Decoder {
             2 => {
                 // PC sample
                 match payload.len() {
-                    1 if payload[0] == 0 => Ok(TracePacket::PCSample { pc: None }),
+                    1 => Ok(TracePacket::PCSample { pc: None }),
                     4 => Ok(TracePacket::PCSample {
-                        pc: Some(u32::from_le_bytes(payload.try_into().unwrap())),
+                        pc: Some(u32::from_le_bytes(payload[..].try_into().unwrap())),
                     }),
                     _ => Err(MalformedPacket::InvalidPCSampleSize { payload }),
                 }
@@ -946,22 +2570,23 @@ impl Decoder {
                 let "???t_tccd" = disc_id; // we have already masked out bit[2:0]
                 let comparator = c;
 
-                match (t, d, payload.len()) {
-                    (0b01, 0, 4) => {
+                match (t, d, payload.value()) {
+                    (0b01, 0, Some(pc)) => {
                         // PC value packet
                         Ok(TracePacket::DataTracePC {
                             comparator,
-                            pc: u32::from_le_bytes(payload.try_into().unwrap()),
+                            pc: pc.as_u32(),
                         })
                     }
-                    (0b01, 1, 2) => {
+                    (0b01, 1, Some(address)) => {
                         // address packet
                         Ok(TracePacket::DataTraceAddress {
                             comparator,
-                            data: payload,
+                            data: Payload::from_slice(&(address.as_u32() as u16).to_le_bytes())
+                                .unwrap(),
                         })
                     }
-                    (0b10, d, _) => {
+                    (0b10, d, Some(value)) => {
                         // data value packet
                         Ok(TracePacket::DataTraceValue {
                             comparator,
@@ -970,6 +2595,7 @@ impl Decoder {
                             } else {
                                 MemoryAccessType::Write
                             },
+                            access_size: value.width(),
                             value: payload,
                         })
                     }
@@ -1082,8 +2708,8 @@ mod tests {
     fn pull_bytes() {
         let mut decoder = Decoder::new(DecoderOptions::default());
         let payload = vec![0b1000_0000, 0b1010_0000, 0b1000_0100, 0b0110_0000];
-        decoder.push(&payload);
-        assert_eq!(decoder.pull_bytes(3).unwrap().len(), 3);
+        decoder.push(&payload).unwrap();
+        assert_eq!(decoder.pull_bytes::<4>(3).unwrap().len(), 3);
     }
 
     #[test]
@@ -1091,8 +2717,15 @@ mod tests {
         let mut decoder = Decoder::new(DecoderOptions::default());
         let payload = vec![0b1000_0000, 0b1010_0000, 0b1000_0100, 0b0110_0000];
         #[rustfmt::skip]
-        decoder.push(&payload);
-        assert_eq!(decoder.pull_payload(), Some(payload));
+        decoder.push(&payload).unwrap();
+        assert_eq!(
+            decoder.pull_payload(MAX_GTS2_PAYLOAD),
+            Some(payload.clone())
+        );
+
+        // Bounded by the maximum payload size
+        decoder.push(&payload[..3]).unwrap();
+        assert_eq!(decoder.pull_payload(2), Some(payload[..2].to_vec()));
     }
 
     #[test]
@@ -0,0 +1,210 @@
+//! A builder for [DecoderOptions] that validates the trace
+//! configuration of the target.
+
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+use crate::{
+    DecoderOptions, GlobalTimestampWidth, PacketFilter, RecoveryPolicy, ValidationMode,
+    MAX_COMPARATORS, MAX_GTS2_PAYLOAD, MAX_STIMULUS_PORTS, RESYNC_LOOKAHEAD,
+};
+
+/// The prescaler applied to the local timestamp clock by the target;
+/// the number of trace clock cycles per local timestamp tick. See
+/// ITM_TCR.TSPrescale.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum LocalTimestampPrescaler {
+    /// No prescaling.
+    #[default]
+    Div1,
+    Div4,
+    Div16,
+    Div64,
+}
+
+impl LocalTimestampPrescaler {
+    /// Returns the divisor of the prescaler.
+    pub fn divisor(&self) -> usize {
+        match self {
+            Self::Div1 => 1,
+            Self::Div4 => 4,
+            Self::Div16 => 16,
+            Self::Div64 => 64,
+        }
+    }
+}
+
+/// A parameter given to [DecoderOptionsBuilder] is invalid.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvalidDecoderOptions {
+    /// The number of stimulus ports is zero or above 256.
+    StimulusPorts(u16),
+
+    /// The number of DWT comparators is above 4.
+    Comparators(u8),
+
+    /// The minimum number of zeros in a Synchronization packet is less
+    /// than 8: a single zero byte.
+    SyncMinZeros(usize),
+
+    /// The input buffer capacity cannot hold the largest packet, or the
+    /// data required by [RecoveryPolicy::Heuristic].
+    Capacity(usize),
+}
+
+impl fmt::Display for InvalidDecoderOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StimulusPorts(ports) => write!(
+                f,
+                "Number of stimulus ports {} is outside the valid range 1-{}",
+                ports, MAX_STIMULUS_PORTS
+            ),
+            Self::Comparators(comparators) => write!(
+                f,
+                "Number of DWT comparators {} is above {}",
+                comparators, MAX_COMPARATORS
+            ),
+            Self::SyncMinZeros(zeros) => write!(
+                f,
+                "A Synchronization packet of at least {} zeros is shorter than a byte",
+                zeros
+            ),
+            Self::Capacity(capacity) => write!(
+                f,
+                "An input buffer of {} bytes is too small to decode all packets",
+                capacity
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidDecoderOptions {}
+
+/// Builds validated [DecoderOptions]. Created by
+/// [DecoderOptions::builder].
+#[derive(Debug, Clone)]
+pub struct DecoderOptionsBuilder {
+    options: DecoderOptions,
+}
+
+impl DecoderOptionsBuilder {
+    /// See [DecoderOptions::only_gts].
+    pub fn only_gts(mut self, only_gts: bool) -> Self {
+        self.options.only_gts = only_gts;
+        self
+    }
+
+    /// See [DecoderOptions::recovery].
+    pub fn recovery(mut self, recovery: RecoveryPolicy) -> Self {
+        self.options.recovery = recovery;
+        self
+    }
+
+    /// See [DecoderOptions::raw_ports].
+    pub fn raw_ports(mut self, raw_ports: bool) -> Self {
+        self.options.raw_ports = raw_ports;
+        self
+    }
+
+    /// See [DecoderOptions::retain_raw].
+    pub fn retain_raw(mut self, retain_raw: bool) -> Self {
+        self.options.retain_raw = retain_raw;
+        self
+    }
+
+    /// See [DecoderOptions::capacity].
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.options.capacity = Some(capacity);
+        self
+    }
+
+    /// See [DecoderOptions::gts2_width].
+    pub fn gts2_width(mut self, width: GlobalTimestampWidth) -> Self {
+        self.options.gts2_width = Some(width);
+        self
+    }
+
+    /// See [DecoderOptions::lts_prescaler].
+    pub fn lts_prescaler(mut self, prescaler: LocalTimestampPrescaler) -> Self {
+        self.options.lts_prescaler = prescaler;
+        self
+    }
+
+    /// See [DecoderOptions::stimulus_ports].
+    pub fn stimulus_ports(mut self, ports: u16) -> Self {
+        self.options.stimulus_ports = ports;
+        self
+    }
+
+    /// See [DecoderOptions::comparators].
+    pub fn comparators(mut self, comparators: u8) -> Self {
+        self.options.comparators = comparators;
+        self
+    }
+
+    /// See [DecoderOptions::sync_min_zeros].
+    pub fn sync_min_zeros(mut self, zeros: usize) -> Self {
+        self.options.sync_min_zeros = zeros;
+        self
+    }
+
+    /// See [DecoderOptions::filter].
+    pub fn filter(mut self, filter: PacketFilter) -> Self {
+        self.options.filter = filter;
+        self
+    }
+
+    /// See [DecoderOptions::validation].
+    pub fn validation(mut self, validation: ValidationMode) -> Self {
+        self.options.validation = validation;
+        self
+    }
+
+    /// Validates the configured parameters and returns the options.
+    pub fn build(self) -> Result<DecoderOptions, InvalidDecoderOptions> {
+        let options = self.options;
+
+        if options.stimulus_ports == 0 || options.stimulus_ports > MAX_STIMULUS_PORTS {
+            return Err(InvalidDecoderOptions::StimulusPorts(options.stimulus_ports));
+        }
+        if options.comparators > MAX_COMPARATORS {
+            return Err(InvalidDecoderOptions::Comparators(options.comparators));
+        }
+        if options.sync_min_zeros < 8 {
+            return Err(InvalidDecoderOptions::SyncMinZeros(options.sync_min_zeros));
+        }
+        if let Some(capacity) = options.capacity {
+            let min = match options.recovery {
+                RecoveryPolicy::Heuristic => RESYNC_LOOKAHEAD,
+                // The largest payload: a 64-bit GlobalTimestamp2 packet
+                _ => MAX_GTS2_PAYLOAD,
+            };
+            // The data spans one more byte while the bitstream is
+            // misaligned after a Synchronization packet
+            if capacity < min + 1 {
+                return Err(InvalidDecoderOptions::Capacity(capacity));
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+impl DecoderOptions {
+    /// Returns a builder that validates the options against the limits
+    /// of the architecture. Starts from [DecoderOptions::default].
+    pub fn builder() -> DecoderOptionsBuilder {
+        DecoderOptionsBuilder {
+            options: DecoderOptions::default(),
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! An inline, fixed-capacity byte buffer for packet payloads.
+
+use core::fmt;
+use core::ops::Deref;
+
+#[cfg(feature = "serde")]
+use serde_crate::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The payload of a packet: up to `N` bytes stored inline, without heap
+/// allocation. Dereferences to a byte slice of its length.
+///
+/// The default capacity of four bytes covers the payload of any source
+/// packet. (Appendix D4.2.8)
+#[derive(Clone, Copy)]
+pub struct Payload<const N: usize = 4> {
+    len: u8,
+    bytes: [u8; N],
+}
+
+impl<const N: usize> Payload<N> {
+    /// The maximum number of bytes a payload can hold.
+    pub const CAPACITY: usize = N;
+
+    /// Returns an empty payload.
+    pub const fn new() -> Self {
+        Self {
+            len: 0,
+            bytes: [0; N],
+        }
+    }
+
+    /// Copies `bytes` into a new payload. Returns `None` if `bytes` is
+    /// longer than [Payload::CAPACITY].
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        let mut payload = Self::new();
+        payload.bytes.get_mut(..bytes.len())?.copy_from_slice(bytes);
+        payload.len = bytes.len() as u8;
+        Some(payload)
+    }
+
+    /// Appends a byte to the payload. Returns `false` if the payload is
+    /// already full.
+    pub fn push(&mut self, b: u8) -> bool {
+        match self.bytes.get_mut(self.len as usize) {
+            Some(slot) => {
+                *slot = b;
+                self.len += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the bytes of the payload.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    /// Interprets the payload as a little-endian integer of its width.
+    /// Returns `None` if the payload is not one, two, or four bytes
+    /// long.
+    pub fn value(&self) -> Option<PayloadValue> {
+        Some(match *self.as_slice() {
+            [b] => PayloadValue::U8(b),
+            [b0, b1] => PayloadValue::U16(u16::from_le_bytes([b0, b1])),
+            [b0, b1, b2, b3] => PayloadValue::U32(u32::from_le_bytes([b0, b1, b2, b3])),
+            _ => return None,
+        })
+    }
+}
+
+/// The size of a memory access or a stimulus port write, and thus the
+/// width of the associated payload. (Appendix D4.2.8, Table D4-4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum AccessSize {
+    /// A one-byte access.
+    Byte,
+
+    /// A two-byte access.
+    Halfword,
+
+    /// A four-byte access.
+    Word,
+}
+
+impl AccessSize {
+    /// Returns the access size of the given number of bytes, if valid.
+    pub fn from_bytes(bytes: usize) -> Option<Self> {
+        match bytes {
+            1 => Some(Self::Byte),
+            2 => Some(Self::Halfword),
+            4 => Some(Self::Word),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of bytes of this access size.
+    pub fn bytes(&self) -> usize {
+        match self {
+            Self::Byte => 1,
+            Self::Halfword => 2,
+            Self::Word => 4,
+        }
+    }
+}
+
+/// A source packet payload interpreted as a little-endian integer of
+/// the width it was written with. See [Payload::value].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum PayloadValue {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+}
+
+impl PayloadValue {
+    /// Returns the width of the value.
+    pub fn width(&self) -> AccessSize {
+        match self {
+            Self::U8(_) => AccessSize::Byte,
+            Self::U16(_) => AccessSize::Halfword,
+            Self::U32(_) => AccessSize::Word,
+        }
+    }
+
+    /// Returns the value zero-extended to 32 bits.
+    pub fn as_u32(&self) -> u32 {
+        match *self {
+            Self::U8(v) => v.into(),
+            Self::U16(v) => v.into(),
+            Self::U32(v) => v,
+        }
+    }
+
+    /// Returns the value as a two's complement integer of its width,
+    /// sign-extended to 32 bits.
+    pub fn as_i32(&self) -> i32 {
+        match *self {
+            Self::U8(v) => (v as i8).into(),
+            Self::U16(v) => (v as i16).into(),
+            Self::U32(v) => v as i32,
+        }
+    }
+
+    /// Returns the bits of the value as an IEEE 754 single-precision
+    /// float. Returns `None` unless the value is 32 bits wide.
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            Self::U32(v) => Some(f32::from_bits(v)),
+            _ => None,
+        }
+    }
+}
+
+/// Formatted as a hexadecimal number zero-padded to the width of the
+/// value. The alternate form is prefixed with `0x`.
+impl fmt::LowerHex for PayloadValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0x")?;
+        }
+        write!(f, "{:01$x}", self.as_u32(), self.width().bytes() * 2)
+    }
+}
+
+impl From<PayloadValue> for Payload {
+    fn from(value: PayloadValue) -> Self {
+        match value {
+            PayloadValue::U8(v) => Self::from_slice(&[v]),
+            PayloadValue::U16(v) => Self::from_slice(&v.to_le_bytes()),
+            PayloadValue::U32(v) => Self::from_slice(&v.to_le_bytes()),
+        }
+        .unwrap()
+    }
+}
+
+impl<const N: usize> Default for Payload<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for Payload<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl<const N: usize> AsRef<[u8]> for Payload<N> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl<const N: usize> fmt::Debug for Payload<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.as_slice()).finish()
+    }
+}
+
+impl<const N: usize, const M: usize> PartialEq<Payload<M>> for Payload<N> {
+    fn eq(&self, other: &Payload<M>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<const N: usize> Eq for Payload<N> {}
+
+impl<const N: usize> PartialEq<[u8]> for Payload<N> {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl<const N: usize> core::hash::Hash for Payload<N> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state)
+    }
+}
+
+impl<'a, const N: usize> core::convert::TryFrom<&'a [u8]> for Payload<N> {
+    type Error = &'a [u8];
+
+    /// Copies `bytes` into a new payload. Returns `bytes` back if they
+    /// do not fit.
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        Self::from_slice(bytes).ok_or(bytes)
+    }
+}
+
+/// Serialized as a sequence of bytes, like a `Vec<u8>`.
+#[cfg(feature = "serde")]
+impl<const N: usize> Serialize for Payload<N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.as_slice())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const N: usize> Deserialize<'de> for Payload<N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor<const N: usize>;
+
+        impl<'de, const N: usize> de::Visitor<'de> for Visitor<N> {
+            type Value = Payload<N>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a sequence of at most {} bytes", N)
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut payload = Payload::new();
+                while let Some(b) = seq.next_element()? {
+                    if !payload.push(b) {
+                        return Err(de::Error::invalid_length(N + 1, &self));
+                    }
+                }
+                Ok(payload)
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                Payload::from_slice(v).ok_or_else(|| de::Error::invalid_length(v.len(), &self))
+            }
+        }
+
+        deserializer.deserialize_seq(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test]
+    fn from_slice() {
+        let payload = Payload::<4>::from_slice(&[1, 2, 3]).unwrap();
+        assert_eq!(payload.len(), 3);
+        assert_eq!(payload.as_slice(), [1, 2, 3]);
+        assert!(Payload::<4>::from_slice(&[1, 2, 3, 4, 5]).is_none());
+    }
+
+    #[test]
+    fn push() {
+        let mut payload = Payload::<2>::new();
+        assert!(payload.push(1));
+        assert!(payload.push(2));
+        assert!(!payload.push(3));
+        assert_eq!(payload, Payload::<4>::from_slice(&[1, 2]).unwrap());
+    }
+
+    #[test]
+    fn value() {
+        let payload = Payload::<4>::from_slice(&[0xFE, 0xFF]).unwrap();
+        let value = payload.value().unwrap();
+        assert_eq!(value, PayloadValue::U16(0xFFFE));
+        assert_eq!(value.width(), AccessSize::Halfword);
+        assert_eq!(value.as_u32(), 0xFFFE);
+        assert_eq!(value.as_i32(), -2);
+        assert_eq!(value.as_f32(), None);
+        assert_eq!(Payload::from(value), payload);
+        assert_eq!(format!("{:x}", value), "fffe");
+        assert_eq!(format!("{:#x}", PayloadValue::U32(0x2a)), "0x0000002a");
+
+        let value = Payload::<4>::from_slice(&1.5f32.to_le_bytes())
+            .unwrap()
+            .value()
+            .unwrap();
+        assert_eq!(value.as_f32(), Some(1.5));
+        assert!(Payload::<4>::from_slice(&[1, 2, 3])
+            .unwrap()
+            .value()
+            .is_none());
+    }
+}
@@ -0,0 +1,146 @@
+//! Streaming decoding directly from a [Read] source.
+
+use crate::{
+    BufferFull, Decoder, DecoderOptions, MalformedPacket, TimestampedTracePackets, TracePacket,
+};
+use std::io::{self, Read};
+use std::{vec, vec::Vec};
+
+/// Number of bytes read from the source at a time.
+const READ_SIZE: usize = 4096;
+
+/// Reads trace data from a [Read] source and decodes it with an owned
+/// [Decoder]. More data is read from the source whenever the decoder
+/// runs out of complete packets. At EOF, the decoder is
+/// [finished](Decoder::finish) and iteration ends once it runs out of
+/// packets again.
+pub struct ItmReader<R> {
+    reader: R,
+    decoder: Decoder,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> ItmReader<R> {
+    pub fn new(reader: R, options: DecoderOptions) -> Self {
+        Self {
+            reader,
+            decoder: Decoder::new(options),
+            buf: vec![0; READ_SIZE],
+        }
+    }
+
+    /// Returns a reference to the underlying decoder.
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    /// Returns a mutable reference to the underlying decoder.
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        &mut self.decoder
+    }
+
+    /// Unwraps this reader, returning the underlying source. Any data
+    /// not yet decoded is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Converts this reader into one that yields
+    /// [TimestampedTracePackets] instead of individual packets.
+    pub fn timestamped(self) -> TimestampedItmReader<R> {
+        TimestampedItmReader { inner: self }
+    }
+
+    /// Reads the next chunk of data from the source into the decoder.
+    /// Returns `false` on EOF. Fails with [BufferFull] if the decoder
+    /// cannot buffer any more data.
+    fn refill(&mut self) -> io::Result<bool> {
+        let size = match self.decoder.remaining_capacity() {
+            Some(0) => return Err(io::Error::other(BufferFull)),
+            Some(remaining) => remaining.min(READ_SIZE),
+            None => READ_SIZE,
+        };
+
+        loop {
+            match self.reader.read(&mut self.buf[..size]) {
+                Ok(0) => return Ok(false),
+                Ok(len) => {
+                    // Never more than the remaining capacity
+                    self.decoder.push(&self.buf[..len]).unwrap();
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for ItmReader<R> {
+    type Item = io::Result<Result<TracePacket, MalformedPacket>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.decoder.pull() {
+                Ok(Some(packet)) => return Some(Ok(Ok(packet))),
+                Err(malformed) => return Some(Ok(Err(malformed))),
+                Ok(None) => match self.refill() {
+                    Ok(true) => continue,
+                    Ok(false) if !self.decoder.is_finished() => self.decoder.finish(),
+                    Ok(false) => return None,
+                    Err(e) => return Some(Err(e)),
+                },
+            }
+        }
+    }
+}
+
+/// As [ItmReader], but yields [TimestampedTracePackets] via
+/// [Decoder::pull_with_timestamp]. At EOF, the decoder is
+/// [finished](Decoder::finish) and packets not yet followed by a local
+/// timestamp are yielded via [Decoder::flush_timestamped].
+/// Created by [ItmReader::timestamped].
+pub struct TimestampedItmReader<R> {
+    inner: ItmReader<R>,
+}
+
+impl<R: Read> TimestampedItmReader<R> {
+    pub fn new(reader: R, options: DecoderOptions) -> Self {
+        ItmReader::new(reader, options).timestamped()
+    }
+
+    /// Returns a reference to the underlying decoder.
+    pub fn decoder(&self) -> &Decoder {
+        self.inner.decoder()
+    }
+
+    /// Returns a mutable reference to the underlying decoder.
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        self.inner.decoder_mut()
+    }
+
+    /// Unwraps this reader, returning the underlying source. Any data
+    /// not yet decoded is lost.
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner()
+    }
+}
+
+impl<R: Read> Iterator for TimestampedItmReader<R> {
+    type Item = io::Result<TimestampedTracePackets>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(set) = self.inner.decoder.pull_with_timestamp() {
+                return Some(Ok(set));
+            }
+
+            match self.inner.refill() {
+                Ok(true) => continue,
+                Ok(false) if !self.inner.decoder.is_finished() => self.inner.decoder.finish(),
+                Ok(false) => return self.inner.decoder.flush_timestamped().map(Ok),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Counters of the data a [Decoder](crate::Decoder) has processed. See
+//! [Decoder::stats](crate::Decoder::stats).
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+use crate::{MalformedPacket, PacketKind};
+
+/// The variant of a [MalformedPacket], without its fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum MalformedKind {
+    InvalidHeader,
+    InvalidHardwarePacket,
+    InvalidHardwareDisc,
+    InvalidExceptionTrace,
+    InvalidPCSampleSize,
+    InvalidGTS2Size,
+    InvalidTimestampSize,
+    InvalidSync,
+    InvalidStimulusPort,
+    InvalidComparator,
+    ReservedBits,
+    ReservedEncoding,
+    InvalidSourcePayload,
+}
+
+impl MalformedKind {
+    /// All malformed packet variants.
+    pub const ALL: [MalformedKind; 13] = [
+        Self::InvalidHeader,
+        Self::InvalidHardwarePacket,
+        Self::InvalidHardwareDisc,
+        Self::InvalidExceptionTrace,
+        Self::InvalidPCSampleSize,
+        Self::InvalidGTS2Size,
+        Self::InvalidTimestampSize,
+        Self::InvalidSync,
+        Self::InvalidStimulusPort,
+        Self::InvalidComparator,
+        Self::ReservedBits,
+        Self::ReservedEncoding,
+        Self::InvalidSourcePayload,
+    ];
+}
+
+impl MalformedPacket {
+    /// Returns the variant of this malformed packet.
+    pub fn kind(&self) -> MalformedKind {
+        match self {
+            Self::InvalidHeader(_) => MalformedKind::InvalidHeader,
+            Self::InvalidHardwarePacket { .. } => MalformedKind::InvalidHardwarePacket,
+            Self::InvalidHardwareDisc { .. } => MalformedKind::InvalidHardwareDisc,
+            Self::InvalidExceptionTrace { .. } => MalformedKind::InvalidExceptionTrace,
+            Self::InvalidPCSampleSize { .. } => MalformedKind::InvalidPCSampleSize,
+            Self::InvalidGTS2Size { .. } => MalformedKind::InvalidGTS2Size,
+            Self::InvalidTimestampSize { .. } => MalformedKind::InvalidTimestampSize,
+            Self::InvalidSync(_) => MalformedKind::InvalidSync,
+            Self::InvalidStimulusPort(_) => MalformedKind::InvalidStimulusPort,
+            Self::InvalidComparator(_) => MalformedKind::InvalidComparator,
+            Self::ReservedBits { .. } => MalformedKind::ReservedBits,
+            Self::ReservedEncoding { .. } => MalformedKind::ReservedEncoding,
+            Self::InvalidSourcePayload { .. } => MalformedKind::InvalidSourcePayload,
+        }
+    }
+}
+
+/// Counters of the data a [Decoder](crate::Decoder) has processed since
+/// it was created or its statistics were last reset.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct DecoderStats {
+    /// Number of packets decoded, indexed by [PacketKind].
+    packets: [u64; PacketKind::ALL.len()],
+
+    /// Number of malformed packets, indexed by [MalformedKind].
+    malformed: [u64; MalformedKind::ALL.len()],
+
+    /// Number of bytes consumed from the bitstream.
+    pub bytes_consumed: u64,
+
+    /// Number of bytes discarded while recovering from malformed
+    /// packets. See [RecoveryPolicy](crate::RecoveryPolicy).
+    pub bytes_discarded: u64,
+
+    /// Number of reserved-bit and reserved-encoding violations
+    /// tolerated in [ValidationMode::Lenient](crate::ValidationMode::Lenient).
+    pub warnings: u64,
+}
+
+impl DecoderStats {
+    /// Returns the number of packets of the given kind decoded,
+    /// including those that did not match
+    /// [DecoderOptions::filter](crate::DecoderOptions::filter).
+    pub fn packets(&self, kind: PacketKind) -> u64 {
+        self.packets[kind as usize]
+    }
+
+    /// Returns the number of packets decoded, of all kinds.
+    pub fn total_packets(&self) -> u64 {
+        self.packets.iter().sum()
+    }
+
+    /// Returns the number of malformed packets of the given variant.
+    pub fn malformed(&self, kind: MalformedKind) -> u64 {
+        self.malformed[kind as usize]
+    }
+
+    /// Returns the number of malformed packets, of all variants.
+    pub fn total_malformed(&self) -> u64 {
+        self.malformed.iter().sum()
+    }
+
+    /// Returns the number of Overflow packets decoded.
+    pub fn overflows(&self) -> u64 {
+        self.packets(PacketKind::Overflow)
+    }
+
+    /// Returns the number of Synchronization packets decoded.
+    pub fn syncs(&self) -> u64 {
+        self.packets(PacketKind::Sync)
+    }
+
+    pub(crate) fn count_packet(&mut self, kind: PacketKind) {
+        self.packets[kind as usize] += 1;
+    }
+
+    pub(crate) fn count_malformed(&mut self, kind: MalformedKind) {
+        self.malformed[kind as usize] += 1;
+    }
+}
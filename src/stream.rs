@@ -0,0 +1,160 @@
+//! [Stream] adapters that decode directly from a
+//! [futures_io::AsyncRead] source.
+
+use crate::{
+    BufferFull, Decoder, DecoderOptions, MalformedPacket, TimestampedTracePackets, TracePacket,
+};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_core::Stream;
+use futures_io::AsyncRead;
+use std::io;
+use std::{vec, vec::Vec};
+
+/// Number of bytes read from the source at a time.
+const READ_SIZE: usize = 4096;
+
+/// The asynchronous analogue of [ItmReader](crate::ItmReader): reads
+/// trace data from an [AsyncRead] source whenever the owned [Decoder]
+/// runs out of complete packets. At EOF, the decoder is
+/// [finished](Decoder::finish) and the stream ends once it runs out of
+/// packets again.
+pub struct ItmStream<R> {
+    reader: R,
+    decoder: Decoder,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> ItmStream<R> {
+    pub fn new(reader: R, options: DecoderOptions) -> Self {
+        Self {
+            reader,
+            decoder: Decoder::new(options),
+            buf: vec![0; READ_SIZE],
+        }
+    }
+
+    /// Returns a reference to the underlying decoder.
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    /// Returns a mutable reference to the underlying decoder.
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        &mut self.decoder
+    }
+
+    /// Unwraps this stream, returning the underlying source. Any data
+    /// not yet decoded is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Converts this stream into one that yields
+    /// [TimestampedTracePackets] instead of individual packets.
+    pub fn timestamped(self) -> TimestampedItmStream<R> {
+        TimestampedItmStream { inner: self }
+    }
+
+    /// Reads the next chunk of data from the source into the decoder.
+    /// Resolves to `false` on EOF. Fails with [BufferFull] if the
+    /// decoder cannot buffer any more data.
+    fn poll_refill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        let size = match self.decoder.remaining_capacity() {
+            Some(0) => {
+                return Poll::Ready(Err(io::Error::other(BufferFull)));
+            }
+            Some(remaining) => remaining.min(READ_SIZE),
+            None => READ_SIZE,
+        };
+
+        loop {
+            match Pin::new(&mut self.reader).poll_read(cx, &mut self.buf[..size]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Ok(false)),
+                Poll::Ready(Ok(len)) => {
+                    // Never more than the remaining capacity
+                    self.decoder.push(&self.buf[..len]).unwrap();
+                    return Poll::Ready(Ok(true));
+                }
+                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for ItmStream<R> {
+    type Item = io::Result<Result<TracePacket, MalformedPacket>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match this.decoder.pull() {
+                Ok(Some(packet)) => return Poll::Ready(Some(Ok(Ok(packet)))),
+                Err(malformed) => return Poll::Ready(Some(Ok(Err(malformed)))),
+                Ok(None) => match this.poll_refill(cx) {
+                    Poll::Ready(Ok(true)) => continue,
+                    Poll::Ready(Ok(false)) if !this.decoder.is_finished() => this.decoder.finish(),
+                    Poll::Ready(Ok(false)) => return Poll::Ready(None),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                    Poll::Pending => return Poll::Pending,
+                },
+            }
+        }
+    }
+}
+
+/// As [ItmStream], but yields [TimestampedTracePackets] via
+/// [Decoder::pull_with_timestamp]. At EOF, the decoder is
+/// [finished](Decoder::finish) and packets not yet followed by a local
+/// timestamp are yielded via [Decoder::flush_timestamped].
+/// Created by [ItmStream::timestamped].
+pub struct TimestampedItmStream<R> {
+    inner: ItmStream<R>,
+}
+
+impl<R: AsyncRead + Unpin> TimestampedItmStream<R> {
+    pub fn new(reader: R, options: DecoderOptions) -> Self {
+        ItmStream::new(reader, options).timestamped()
+    }
+
+    /// Returns a reference to the underlying decoder.
+    pub fn decoder(&self) -> &Decoder {
+        self.inner.decoder()
+    }
+
+    /// Returns a mutable reference to the underlying decoder.
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        self.inner.decoder_mut()
+    }
+
+    /// Unwraps this stream, returning the underlying source. Any data
+    /// not yet decoded is lost.
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner()
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for TimestampedItmStream<R> {
+    type Item = io::Result<TimestampedTracePackets>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let inner = &mut self.get_mut().inner;
+        loop {
+            if let Some(set) = inner.decoder.pull_with_timestamp() {
+                return Poll::Ready(Some(Ok(set)));
+            }
+
+            match inner.poll_refill(cx) {
+                Poll::Ready(Ok(true)) => continue,
+                Poll::Ready(Ok(false)) if !inner.decoder.is_finished() => inner.decoder.finish(),
+                Poll::Ready(Ok(false)) => {
+                    return Poll::Ready(inner.decoder.flush_timestamped().map(Ok))
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Reconstruction of a single absolute timeline from local and global
+//! timestamp packets.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+use crate::{TimestampDataRelation, TimingConfig, TracePacket};
+
+/// Number of lower-order bits of a global timestamp carried by a
+/// GlobalTimestamp1 packet. (Appendix D4.2.5)
+const GTS1_BITS: u32 = 26;
+
+/// A break in a [Timeline], after which its ticks do not follow from
+/// the local timestamps before it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum Discontinuity {
+    /// An Overflow packet was received: local timestamps may have been
+    /// lost, and the timeline may lag behind until the next global
+    /// timestamp.
+    Overflow,
+
+    /// A global timestamp re-anchored the timeline. The timeline
+    /// continues from the later of the two tick values, so that it
+    /// never decreases.
+    Reanchor {
+        /// Ticks of the timeline before the global timestamp.
+        from: u64,
+
+        /// Ticks of the global timestamp.
+        to: u64,
+    },
+
+    /// A GlobalTimestamp1 packet with its `clkch` flag set was
+    /// received: the input clock to the ITM has changed. The timeline
+    /// keeps counting with the clocks it was created with, so its ticks
+    /// after the change are not comparable to those before it. Use a
+    /// [WallClock](crate::WallClock) to convert timestamps across clock
+    /// changes.
+    ClockChange,
+}
+
+/// The packets preceding a local timestamp and their position on a
+/// [Timeline].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct TimelineGroup {
+    /// Ticks of the trace clock since the start of the timeline.
+    pub ticks: u64,
+
+    /// How the local timestamp relates to `packets`.
+    pub data_relation: TimestampDataRelation,
+
+    /// The packets, excluding timestamps, received since the last
+    /// local timestamp.
+    pub packets: Vec<TracePacket>,
+
+    /// Discontinuities of the timeline since the last local timestamp.
+    pub discontinuities: Vec<Discontinuity>,
+}
+
+/// Combines local and global timestamps into one monotonic 64-bit tick
+/// value per group of packets, counted in trace clock cycles. Fed with
+/// decoded packets; see [Timeline::push].
+///
+/// Local timestamps advance the timeline by their prescaled value.
+/// Global timestamps re-anchor it: a GlobalTimestamp1 packet updates the
+/// lower 26 bits of the last GlobalTimestamp2 packet, unless its `wrap`
+/// or `clkch` flag is set, in which case the timeline is re-anchored
+/// once the next GlobalTimestamp2 packet provides the upper bits. A set
+/// `clkch` flag is also reported as [Discontinuity::ClockChange].
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Timeline {
+    /// Clock configuration of the target. If `None`, the global
+    /// timestamp clock is assumed to be the trace clock, and local
+    /// timestamps are not prescaled.
+    timing: Option<TimingConfig>,
+
+    /// Current ticks of the timeline.
+    ticks: u64,
+
+    /// Upper bits of the global timestamp of the last GlobalTimestamp2
+    /// packet.
+    gts_upper: Option<u64>,
+
+    /// Lower bits of a GlobalTimestamp1 packet awaiting the upper bits
+    /// of a GlobalTimestamp2 packet.
+    gts_lower: Option<u64>,
+
+    /// Packets received since the last local timestamp.
+    packets: Vec<TracePacket>,
+
+    /// Discontinuities since the last local timestamp.
+    discontinuities: Vec<Discontinuity>,
+}
+
+impl Timeline {
+    pub fn new(timing: Option<TimingConfig>) -> Self {
+        Self {
+            timing,
+            ticks: 0,
+            gts_upper: None,
+            gts_lower: None,
+            packets: Vec::new(),
+            discontinuities: Vec::new(),
+        }
+    }
+
+    /// Returns the current ticks of the timeline.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Feeds the next decoded packet to the timeline. Returns the group
+    /// of packets received since the last local timestamp if `packet`
+    /// is a local timestamp.
+    pub fn push(&mut self, packet: TracePacket) -> Option<TimelineGroup> {
+        match packet {
+            TracePacket::LocalTimestamp1 { ts, data_relation } => {
+                return Some(self.advance(ts, data_relation))
+            }
+            TracePacket::LocalTimestamp2 { ts } => {
+                return Some(self.advance(ts.into(), TimestampDataRelation::Sync))
+            }
+            TracePacket::GlobalTimestamp1 { ts, wrap, clkch } => {
+                if clkch {
+                    self.discontinuities.push(Discontinuity::ClockChange);
+                }
+                match self.gts_upper {
+                    // Upper bits unchanged
+                    Some(upper) if !wrap && !clkch => self.reanchor((upper << GTS1_BITS) | ts),
+                    _ => self.gts_lower = Some(ts),
+                }
+            }
+            TracePacket::GlobalTimestamp2 { ts } => {
+                self.gts_upper = Some(ts);
+                if let Some(lower) = self.gts_lower.take() {
+                    self.reanchor((ts << GTS1_BITS) | lower);
+                }
+            }
+            TracePacket::Overflow => {
+                self.discontinuities.push(Discontinuity::Overflow);
+                self.packets.push(packet);
+            }
+            packet => self.packets.push(packet),
+        }
+
+        None
+    }
+
+    /// Advances the timeline by a local timestamp and returns the
+    /// packets it relates to.
+    fn advance(&mut self, lts: u64, data_relation: TimestampDataRelation) -> TimelineGroup {
+        let divisor = self
+            .timing
+            .as_ref()
+            .map_or(1, |timing| timing.lts_prescaler.divisor() as u64);
+        self.ticks = self.ticks.saturating_add(lts.saturating_mul(divisor));
+
+        TimelineGroup {
+            ticks: self.ticks,
+            data_relation,
+            packets: core::mem::take(&mut self.packets),
+            discontinuities: core::mem::take(&mut self.discontinuities),
+        }
+    }
+
+    /// Re-anchors the timeline on a global timestamp.
+    fn reanchor(&mut self, gts: u64) {
+        let to = match &self.timing {
+            Some(timing) => (gts as u128 * timing.trace_clock_hz.get() as u128
+                / timing.gts_clock_hz.get() as u128)
+                .try_into()
+                .unwrap_or(u64::MAX),
+            None => gts,
+        };
+
+        if to != self.ticks {
+            self.discontinuities.push(Discontinuity::Reanchor {
+                from: self.ticks,
+                to,
+            });
+            self.ticks = self.ticks.max(to);
+        }
+    }
+}
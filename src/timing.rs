@@ -0,0 +1,244 @@
+//! Conversion of [Timestamp]s into the time of the target.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::num::NonZeroU32;
+use core::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+use crate::{DecoderOptions, LocalTimestampPrescaler, Timestamp, TimestampedTracePackets};
+
+/// Maximum value of the local timestamp counter: the width of the
+/// timestamp of a LocalTimestamp1 packet. (Appendix D4.2.4)
+const MAX_LTS: u64 = (1 << 28) - 1;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// The clocks of the target that [Timestamp]s are counted in.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct TimingConfig {
+    /// Frequency of the clock the local timestamp counter is derived
+    /// from, in Hz: the processor clock, or the TPIU asynchronous clock
+    /// if ITM_TCR.SWOENA is set.
+    pub trace_clock_hz: NonZeroU32,
+
+    /// The local timestamp prescaler configured on the target. Only
+    /// bounds the error of diverged timestamps, and scales the local
+    /// timestamps of a [Timeline](crate::Timeline): [Timestamp::delta]
+    /// is already scaled by
+    /// [DecoderOptions::lts_prescaler](crate::DecoderOptions::lts_prescaler),
+    /// and not scaled again. See [TimingConfig::new].
+    pub lts_prescaler: LocalTimestampPrescaler,
+
+    /// Frequency of the global timestamp clock, in Hz.
+    pub gts_clock_hz: NonZeroU32,
+}
+
+impl TimingConfig {
+    /// Creates a clock configuration with the local timestamp prescaler
+    /// of the decoder options the timestamps are decoded with.
+    pub fn new(
+        options: &DecoderOptions,
+        trace_clock_hz: NonZeroU32,
+        gts_clock_hz: NonZeroU32,
+    ) -> Self {
+        Self {
+            trace_clock_hz,
+            lts_prescaler: options.lts_prescaler,
+            gts_clock_hz,
+        }
+    }
+
+    /// Returns the number of nanoseconds `ticks` cycles of a clock of the
+    /// given frequency take, saturating at `u64::MAX`.
+    fn nanos(ticks: u64, hz: NonZeroU32) -> u64 {
+        (ticks as u128 * NANOS_PER_SEC / hz.get() as u128)
+            .try_into()
+            .unwrap_or(u64::MAX)
+    }
+}
+
+impl Timestamp {
+    /// Returns the time of the timestamp in nanoseconds: the global
+    /// timestamp [Timestamp::base], if any, plus the local timestamp
+    /// [Timestamp::delta], if any. With a base, this is the time of the
+    /// global timestamp clock of the target, which need not have
+    /// started with the trace. Without a base, the time is relative to
+    /// the first local timestamp. Returns `None` if neither is known.
+    ///
+    /// The times of timestamps before and after the first global
+    /// timestamp thus have different origins. Use a [WallClock] to
+    /// convert a sequence of timestamps from a single origin.
+    pub fn nanos(&self, timing: &TimingConfig) -> Option<u64> {
+        if self.base.is_none() && self.delta.is_none() {
+            return None;
+        }
+
+        let base = TimingConfig::nanos(self.base.unwrap_or(0) as u64, timing.gts_clock_hz);
+        let delta = TimingConfig::nanos(self.delta.unwrap_or(0) as u64, timing.trace_clock_hz);
+        Some(base.saturating_add(delta))
+    }
+
+    /// As [Timestamp::nanos], but as a [Duration].
+    pub fn duration(&self, timing: &TimingConfig) -> Option<Duration> {
+        self.nanos(timing).map(Duration::from_nanos)
+    }
+
+    /// Returns how much later than [Timestamp::duration] the true time
+    /// may be. Zero unless the timestamp has [Timestamp::diverged], in
+    /// which case the local timestamp counter may have wrapped: by its
+    /// maximum value of 2^28 - 1 prescaled ticks.
+    pub fn error_bound(&self, timing: &TimingConfig) -> Duration {
+        if !self.diverged {
+            return Duration::ZERO;
+        }
+
+        let ticks = MAX_LTS * timing.lts_prescaler.divisor() as u64;
+        Duration::from_nanos(TimingConfig::nanos(ticks, timing.trace_clock_hz))
+    }
+}
+
+impl TimestampedTracePackets {
+    /// Returns the time of the packets in nanoseconds. See
+    /// [Timestamp::nanos].
+    pub fn nanos(&self, timing: &TimingConfig) -> Option<u64> {
+        self.timestamp.nanos(timing)
+    }
+
+    /// Returns the time of the packets. See [Timestamp::duration].
+    pub fn duration(&self, timing: &TimingConfig) -> Option<Duration> {
+        self.timestamp.duration(timing)
+    }
+
+    /// Returns the error bound of [TimestampedTracePackets::duration].
+    /// See [Timestamp::error_bound].
+    pub fn error_bound(&self, timing: &TimingConfig) -> Duration {
+        self.timestamp.error_bound(timing)
+    }
+
+    /// Returns the earliest and latest time the packets may have been
+    /// generated: from [TimestampedTracePackets::earliest], or zero if
+    /// its time is unknown, to the timestamp plus its error bound. The
+    /// interval is empty, save for the error bound, if the timestamp is
+    /// exact. If the packets are [TimestampedTracePackets::incomplete],
+    /// they were generated at or after the timestamp, and the latest
+    /// time is [Duration::MAX]. Returns `None` if the timestamp is
+    /// unknown.
+    pub fn interval(&self, timing: &TimingConfig) -> Option<(Duration, Duration)> {
+        if self.incomplete {
+            return Some((self.duration(timing)?, Duration::MAX));
+        }
+
+        let latest = self.duration(timing)? + self.error_bound(timing);
+        let earliest = match &self.earliest {
+            Some(earliest) => earliest.duration(timing).unwrap_or(Duration::ZERO),
+            None => self.duration(timing)?,
+        };
+        Some((earliest, latest))
+    }
+}
+
+/// Converts [TimestampedTracePackets] into the time of the target
+/// across changes of the input clock to the ITM. See
+/// [TimestampedTracePackets::clock_changed].
+///
+/// Each clock segment is converted with its own [TimingConfig]. Time is
+/// counted from the first timestamp converted, and every later segment
+/// starts at the last time converted before it. Within a segment, the
+/// first global timestamp continues from the last time converted before
+/// it, and later ones are relative to it: unlike [Timestamp::nanos], the
+/// absolute value of the global timestamp clock is not used. Fed with
+/// every set of packets, in order; see [WallClock::convert].
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct WallClock {
+    /// Clock configuration of each segment. The last configuration
+    /// applies to all later segments.
+    segments: Vec<TimingConfig>,
+
+    /// Index of the current segment.
+    segment: usize,
+
+    /// Time at the start of the current segment, in nanoseconds.
+    origin: u64,
+
+    /// The first global timestamp of the current segment, and its time
+    /// in nanoseconds.
+    anchor: Option<(usize, u64)>,
+
+    /// Time of the last converted set of packets, in nanoseconds.
+    last: u64,
+}
+
+impl WallClock {
+    /// Creates a wall clock with the clock configuration of the first
+    /// segment.
+    pub fn new(timing: TimingConfig) -> Self {
+        Self {
+            segments: alloc::vec![timing],
+            segment: 0,
+            origin: 0,
+            anchor: None,
+            last: 0,
+        }
+    }
+
+    /// Appends the clock configuration of the segment after the last
+    /// added one. May be called upon a clock change, before converting
+    /// the packets that report it.
+    pub fn add_segment(&mut self, timing: TimingConfig) {
+        self.segments.push(timing);
+    }
+
+    /// Returns the index of the current segment: the number of clock
+    /// changes converted thus far.
+    pub fn segment(&self) -> usize {
+        self.segment
+    }
+
+    /// Returns the clock configuration of the current segment.
+    pub fn timing(&self) -> &TimingConfig {
+        let last = self.segments.len() - 1;
+        &self.segments[self.segment.min(last)]
+    }
+
+    /// Returns the time of the packets, starting a new segment if
+    /// [TimestampedTracePackets::clock_changed] is set. Returns `None`
+    /// if the timestamp is unknown.
+    pub fn convert(&mut self, packets: &TimestampedTracePackets) -> Option<Duration> {
+        if packets.clock_changed {
+            self.segment += 1;
+            self.origin = self.last;
+            self.anchor = None;
+        }
+
+        let timing = self.timing().clone();
+        let ts = &packets.timestamp;
+        let delta = TimingConfig::nanos(ts.delta.unwrap_or(0) as u64, timing.trace_clock_hz);
+        let nanos = match ts.base {
+            Some(base) => {
+                let (base0, nanos0) = *self.anchor.get_or_insert((base, self.last));
+                let elapsed = base.saturating_sub(base0) as u64;
+                nanos0
+                    .saturating_add(TimingConfig::nanos(elapsed, timing.gts_clock_hz))
+                    .saturating_add(delta)
+            }
+            None if ts.delta.is_some() => self.origin.saturating_add(delta),
+            None => return None,
+        };
+
+        self.last = nanos;
+        Some(Duration::from_nanos(nanos))
+    }
+}
@@ -0,0 +1,173 @@
+#![cfg(any(feature = "tokio", feature = "futures"))]
+
+use itm_decode::*;
+
+#[rustfmt::skip]
+const TRACE_DATA: [u8; 9] = [
+    // Instrumentation packet
+    0b0000_1010,
+    b'h',
+    b'i',
+
+    // Malformed header
+    0b1111_1111,
+
+    // PC sample (sleeping)
+    0b0001_0101,
+    0b0000_0000,
+
+    // LTS1
+    0b1100_0000,
+    0b1100_1001,
+    0b0000_0001,
+];
+
+#[cfg(feature = "tokio")]
+#[test]
+fn codec_decode() {
+    use bytes::BytesMut;
+    use tokio_util::codec::Decoder as _;
+
+    let mut codec = ItmCodec::new(DecoderOptions::default());
+    let mut src = BytesMut::from(&TRACE_DATA[..2]);
+    assert_eq!(codec.decode(&mut src).unwrap(), None);
+    assert!(src.is_empty());
+
+    src.extend_from_slice(&TRACE_DATA[2..]);
+    assert_eq!(
+        codec.decode(&mut src).unwrap(),
+        Some(Ok(TracePacket::Instrumentation {
+            port: 1,
+            payload: Payload::from_slice(b"hi").unwrap(),
+        }))
+    );
+    assert_eq!(
+        codec.decode(&mut src).unwrap(),
+        Some(Err(MalformedPacket::InvalidHardwareDisc {
+            disc_id: 31,
+            size: 3,
+        }))
+    );
+    assert_eq!(
+        codec.decode(&mut src).unwrap(),
+        Some(Ok(TracePacket::PCSample { pc: None }))
+    );
+
+    let mut codec = TimestampedItmCodec::new(DecoderOptions::default());
+    let mut src = BytesMut::from(&TRACE_DATA[..]);
+    let set = codec.decode(&mut src).unwrap().unwrap();
+    assert_eq!(set.packets.len(), 2);
+    assert_eq!(set.timestamp.delta, Some(0b1_1001001));
+    assert_eq!(codec.decode_eof(&mut src).unwrap(), None);
+
+    // Packets not followed by a local timestamp are flushed at EOF
+    let mut src = BytesMut::from(&TRACE_DATA[4..6]);
+    assert_eq!(codec.decode(&mut src).unwrap(), None);
+    let set = codec.decode_eof(&mut src).unwrap().unwrap();
+    assert!(set.incomplete);
+    assert_eq!(set.packets, [TracePacket::PCSample { pc: None }]);
+    assert_eq!(codec.decode_eof(&mut src).unwrap(), None);
+
+    // A malformed packet precedes the final packets
+    let mut codec = TimestampedItmCodec::new(DecoderOptions {
+        recovery: RecoveryPolicy::Heuristic,
+        ..DecoderOptions::default()
+    });
+    let mut src = BytesMut::from(&TRACE_DATA[..6]);
+    assert_eq!(codec.decode(&mut src).unwrap(), None);
+    let set = codec.decode_eof(&mut src).unwrap().unwrap();
+    assert_eq!(set.packets.len(), 2);
+    assert_eq!(set.malformed_packets.len(), 1);
+    assert_eq!(codec.decoder().buffered_len(), 0);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn codec_decode_bounded() {
+    use bytes::BytesMut;
+    use tokio_util::codec::Decoder as _;
+
+    let options = |capacity| DecoderOptions {
+        capacity: Some(capacity),
+        ..DecoderOptions::default()
+    };
+
+    // Data the decoder does not accept is left in the source buffer
+    let mut codec = ItmCodec::new(options(3));
+    let mut src = BytesMut::from(&TRACE_DATA[..]);
+    assert!(matches!(codec.decode(&mut src), Ok(Some(Ok(_)))));
+    assert_eq!(src.len(), TRACE_DATA.len() - 3);
+    let mut packets = 1;
+    while let Some(packet) = codec.decode(&mut src).unwrap() {
+        assert!(packet.is_ok() || packets == 1);
+        packets += 1;
+    }
+    assert_eq!(packets, 4);
+    assert!(src.is_empty());
+
+    // Data following a malformed packet is recovered at EOF
+    let mut codec = ItmCodec::new(DecoderOptions {
+        recovery: RecoveryPolicy::Heuristic,
+        ..DecoderOptions::default()
+    });
+    let mut src = BytesMut::from(&TRACE_DATA[..]);
+    assert!(matches!(codec.decode(&mut src), Ok(Some(Ok(_)))));
+    assert!(matches!(codec.decode(&mut src), Ok(Some(Err(_)))));
+    assert_eq!(codec.decode(&mut src).unwrap(), None);
+    assert_eq!(
+        codec.decode_eof(&mut src).unwrap(),
+        Some(Ok(TracePacket::PCSample { pc: None }))
+    );
+    assert!(matches!(codec.decode_eof(&mut src), Ok(Some(Ok(_)))));
+    assert_eq!(codec.decode_eof(&mut src).unwrap(), None);
+
+    // An instrumentation packet payload does not fit in one byte
+    let mut codec = ItmCodec::new(options(1));
+    let mut src = BytesMut::from(&TRACE_DATA[..]);
+    assert_eq!(codec.decode(&mut src).unwrap(), None);
+    let err = codec.decode(&mut src).unwrap_err();
+    assert_eq!(err.into_inner().unwrap().downcast_ref(), Some(&BufferFull));
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn stream_decode() {
+    use futures::{executor::block_on, io::Cursor, StreamExt};
+
+    let stream = ItmStream::new(Cursor::new(TRACE_DATA), DecoderOptions::default());
+    let packets = block_on(stream.collect::<Vec<_>>());
+    assert_eq!(packets.len(), 4);
+    assert!(packets.iter().all(|p| p.is_ok()));
+
+    let stream = ItmStream::new(
+        Cursor::new(TRACE_DATA),
+        DecoderOptions {
+            recovery: RecoveryPolicy::Heuristic,
+            ..DecoderOptions::default()
+        },
+    );
+    let packets = block_on(stream.collect::<Vec<_>>());
+    assert_eq!(packets.len(), 4);
+
+    let stream = TimestampedItmStream::new(Cursor::new(TRACE_DATA), DecoderOptions::default());
+    let sets = block_on(stream.collect::<Vec<_>>());
+    assert_eq!(sets.len(), 1);
+    assert_eq!(sets[0].as_ref().unwrap().malformed_packets.len(), 1);
+
+    let stream =
+        TimestampedItmStream::new(Cursor::new(&TRACE_DATA[..6]), DecoderOptions::default());
+    let sets = block_on(stream.collect::<Vec<_>>());
+    assert_eq!(sets.len(), 1);
+    assert!(sets[0].as_ref().unwrap().incomplete);
+
+    let stream = TimestampedItmStream::new(
+        Cursor::new(&TRACE_DATA[..6]),
+        DecoderOptions {
+            recovery: RecoveryPolicy::Heuristic,
+            ..DecoderOptions::default()
+        },
+    );
+    let sets = block_on(stream.collect::<Vec<_>>());
+    assert_eq!(sets.len(), 1);
+    assert_eq!(sets[0].as_ref().unwrap().packets.len(), 2);
+}
@@ -1,19 +1,80 @@
 use itm_decode::*;
 
+/// The location of a packet spanning bytes `start..end` of an aligned
+/// bitstream.
+fn span(start: u64, end: u64) -> Location {
+    Location {
+        start: StreamPosition {
+            byte: start,
+            bit: 0,
+        },
+        end: StreamPosition { byte: end, bit: 0 },
+    }
+}
+
 #[test]
 fn decode_sync_packet() {
     let mut trace_data: Vec<u8> = [0; 47 / 8].to_vec();
     trace_data.push(1 << 7);
 
     let mut decoder = Decoder::new(DecoderOptions::default());
-    decoder.push(&trace_data);
+    decoder.push(&trace_data).unwrap();
     assert_eq!(decoder.pull(), Ok(Some(TracePacket::Sync)));
 }
 
+#[test]
+fn decode_misaligned_sync_packet() {
+    #[rustfmt::skip]
+    let trace_data = [
+        // Sync (50 zeros and a set bit)
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b1000_0100,
+
+        // Overflow, misaligned by three bits
+        0b0000_0011,
+    ];
+
+    // The result must not depend on how the bitstream is chunked
+    for chunk_size in 1..=trace_data.len() {
+        let mut decoder = Decoder::new(DecoderOptions::default());
+        let mut packets = vec![];
+        for chunk in trace_data.chunks(chunk_size) {
+            decoder.push(chunk).unwrap();
+            while let Some(packet) = decoder.pull().unwrap() {
+                packets.push(packet);
+            }
+        }
+
+        assert_eq!(packets, [TracePacket::Sync, TracePacket::Overflow]);
+    }
+}
+
+#[test]
+fn decode_split_packet() {
+    let mut decoder = Decoder::new(DecoderOptions::default());
+    decoder.push(&[0b0000_1010]).unwrap();
+    assert_eq!(decoder.pull(), Ok(None));
+    decoder.push(b"h").unwrap();
+    assert_eq!(decoder.pull(), Ok(None));
+    decoder.push(b"i").unwrap();
+    assert_eq!(
+        decoder.pull(),
+        Ok(Some(TracePacket::Instrumentation {
+            port: 1,
+            payload: Payload::from_slice(b"hi").unwrap(),
+        }))
+    );
+}
+
 #[test]
 fn decode_overflow_packet() {
     let mut decoder = Decoder::new(DecoderOptions::default());
-    decoder.push(&[0b0111_0000]);
+    decoder.push(&[0b0111_0000]).unwrap();
     assert_eq!(decoder.pull(), Ok(Some(TracePacket::Overflow)));
 }
 
@@ -29,7 +90,7 @@ fn decode_local_timestamp_packets() {
 
             // LTS2
             0b0101_0000,
-        ]);
+        ]).unwrap();
 
     for packet in [
         TracePacket::LocalTimestamp1 {
@@ -71,7 +132,7 @@ fn decode_global_timestamp_packets() {
             0b1000_0001,
             0b1111_0100,
             0b0000_0111,
-        ]);
+        ]).unwrap();
 
     for packet in [
         TracePacket::GlobalTimestamp1 {
@@ -98,7 +159,7 @@ fn decode_extention_packet() {
     #[rustfmt::skip]
         decoder.push(&[
             0b0111_1000,
-        ]);
+        ]).unwrap();
 
     assert_eq!(
         decoder.pull(),
@@ -106,6 +167,44 @@ fn decode_extention_packet() {
     );
 }
 
+#[test]
+fn decode_paged_instrumentation_packet() {
+    #[rustfmt::skip]
+    let trace_data = [
+        // Extension (page 2)
+        0b0010_1000,
+
+        // Instrumentation (port 3)
+        0b0001_1001,
+        b'h',
+    ];
+
+    let mut decoder = Decoder::new(DecoderOptions::default());
+    decoder.push(&trace_data).unwrap();
+    assert_eq!(decoder.pull(), Ok(Some(TracePacket::Extension { page: 2 })));
+    assert_eq!(
+        decoder.pull(),
+        Ok(Some(TracePacket::Instrumentation {
+            port: 2 * 32 + 3,
+            payload: Payload::from_slice(b"h").unwrap(),
+        }))
+    );
+
+    let mut decoder = Decoder::new(DecoderOptions {
+        raw_ports: true,
+        ..DecoderOptions::default()
+    });
+    decoder.push(&trace_data).unwrap();
+    assert_eq!(decoder.pull(), Ok(Some(TracePacket::Extension { page: 2 })));
+    assert_eq!(
+        decoder.pull(),
+        Ok(Some(TracePacket::Instrumentation {
+            port: 3,
+            payload: Payload::from_slice(b"h").unwrap(),
+        }))
+    );
+}
+
 #[test]
 fn decode_instrumentation_packet() {
     let mut decoder = Decoder::new(DecoderOptions::default());
@@ -116,19 +215,19 @@ fn decode_instrumentation_packet() {
             0b0000_1111,
             0b0011_1111,
             0b1111_1111,
-        ]);
+        ]).unwrap();
 
     assert_eq!(
         decoder.pull(),
         Ok(Some(TracePacket::Instrumentation {
-            port: 0b1000_1,
+            port: 0b1_0001,
             #[rustfmt::skip]
-                payload: [
+                payload: Payload::from_slice(&[
                     0b0000_0011,
                     0b0000_1111,
                     0b0011_1111,
                     0b1111_1111,
-                ].to_vec(),
+                ]).unwrap(),
         }))
     );
 }
@@ -140,7 +239,7 @@ fn decode_eventcounterwrap_packet() {
         decoder.push(&[
             0b0000_0101,
             0b0010_1010,
-        ]);
+        ]).unwrap();
 
     assert_eq!(
         decoder.pull(),
@@ -163,15 +262,67 @@ fn decode_exceptiontrace_packet() {
             0b0000_1110,
             0b0010_0000,
             0b0011_0000
-        ]);
+        ]).unwrap();
 
     assert_eq!(
         decoder.pull(),
         Ok(Some(TracePacket::ExceptionTrace {
-            exception: cortex_m::VectActive::Interrupt { irqn: 32 },
+            exception: ExceptionType::Interrupt { irqn: 16 },
             action: ExceptionAction::Returned,
         }))
     );
+
+    // Exception numbers above 255
+    #[rustfmt::skip]
+    decoder.push(&[
+        0b0000_1110,
+        0b1111_1111,
+        0b0001_0001,
+
+        0b0000_1110,
+        0b0000_1000,
+        0b0001_0000,
+    ]).unwrap();
+    assert_eq!(
+        decoder.pull(),
+        Ok(Some(TracePacket::ExceptionTrace {
+            exception: ExceptionType::Interrupt { irqn: 495 },
+            action: ExceptionAction::Entered,
+        }))
+    );
+    assert_eq!(
+        decoder.pull(),
+        Ok(Some(TracePacket::ExceptionTrace {
+            exception: ExceptionType::Reserved(8),
+            action: ExceptionAction::Entered,
+        }))
+    );
+}
+
+#[cfg(feature = "cortex-m")]
+#[test]
+fn exception_type_vect_active() {
+    use itm_decode::cortex_m::{Exception, VectActive};
+
+    for (exception, vect) in [
+        (ExceptionType::ThreadMode, Some(VectActive::ThreadMode)),
+        (ExceptionType::Reset, None),
+        (
+            ExceptionType::SysTick,
+            Some(VectActive::Exception(Exception::SysTick)),
+        ),
+        (ExceptionType::Reserved(13), None),
+        (
+            ExceptionType::Interrupt { irqn: 239 },
+            Some(VectActive::Interrupt { irqn: 239 }),
+        ),
+        (ExceptionType::Interrupt { irqn: 240 }, None),
+    ] {
+        assert_eq!(exception.to_vect_active(), vect);
+        if let Some(vect) = vect {
+            assert_eq!(ExceptionType::from(vect), exception);
+        }
+    }
 }
 
 #[test]
@@ -189,7 +340,7 @@ fn decode_pcsample_packet() {
             // PC sample (sleeping)
             0b0001_0101,
             0b0000_0000,
-        ]);
+        ]).unwrap();
 
     for packet in [
         TracePacket::PCSample {
@@ -213,7 +364,7 @@ fn decode_datatracepc_packet() {
             0b0000_1111,
             0b0011_1111,
             0b1111_1111,
-        ]);
+        ]).unwrap();
 
     assert_eq!(
         decoder.pull(),
@@ -232,17 +383,17 @@ fn decode_datatraceaddress_packet() {
             0b0110_1110,
             0b0000_0011,
             0b0000_1111,
-        ]);
+        ]).unwrap();
 
     assert_eq!(
         decoder.pull(),
         Ok(Some(TracePacket::DataTraceAddress {
             comparator: 0b10,
             #[rustfmt::skip]
-                data: [
+                data: Payload::from_slice(&[
                     0b0000_0011,
                     0b0000_1111,
-                ].to_vec(),
+                ]).unwrap(),
         }))
     );
 }
@@ -267,36 +418,39 @@ fn decode_datatracevalue_packet() {
             // one-byte (byte) payload
             0b1010_1101,
             0b0000_0011,
-        ]);
+        ]).unwrap();
 
     for packet in [
         TracePacket::DataTraceValue {
             comparator: 0b10,
             access_type: MemoryAccessType::Write,
+            access_size: AccessSize::Word,
             #[rustfmt::skip]
-                value: [
+                value: Payload::from_slice(&[
                     0b0000_0011,
                     0b0000_1111,
                     0b0011_1111,
                     0b1111_1111,
-                ].to_vec(),
+                ]).unwrap(),
         },
         TracePacket::DataTraceValue {
             comparator: 0b10,
             access_type: MemoryAccessType::Write,
+            access_size: AccessSize::Halfword,
             #[rustfmt::skip]
-                value: [
+                value: Payload::from_slice(&[
                     0b0000_0011,
                     0b0000_1111,
-                ].to_vec(),
+                ]).unwrap(),
         },
         TracePacket::DataTraceValue {
             comparator: 0b10,
             access_type: MemoryAccessType::Write,
+            access_size: AccessSize::Byte,
             #[rustfmt::skip]
-                value: [
+                value: Payload::from_slice(&[
                     0b0000_0011,
-                ].to_vec(),
+                ]).unwrap(),
         },
     ]
     .iter()
@@ -386,7 +540,7 @@ fn pull_with_timestamp() {
             // Pull!
 
             // Pull!
-        ]);
+        ]).unwrap();
 
     for set in [
         Some(TimestampedTracePackets {
@@ -397,46 +551,72 @@ fn pull_with_timestamp() {
             ]
             .into(),
             malformed_packets: [].into(),
+            raw: vec![],
             timestamp: Timestamp {
                 base: Some((0b1_0010001_1110100_0111101 << 26) | (0b0_0000100_0100000_0000000)),
                 delta: Some(0b1_1001001),
                 data_relation: Some(TimestampDataRelation::Sync),
                 diverged: false,
             },
+            earliest: None,
+            clock_changed: false,
+            incomplete: false,
             packets_consumed: 6,
+            location: span(0, 19),
         }),
         Some(TimestampedTracePackets {
             packets: [TracePacket::PCSample { pc: None }].into(),
             malformed_packets: [].into(),
+            raw: vec![],
             timestamp: Timestamp {
                 base: Some((0b1_0010001_1110100_0111101 << 26) | (0b0_0000100_0100000_0000000)),
                 delta: Some(0b1_1001001 * 2),
                 data_relation: Some(TimestampDataRelation::Sync),
                 diverged: false,
             },
+            earliest: None,
+            clock_changed: false,
+            incomplete: false,
             packets_consumed: 2,
+            location: span(19, 24),
         }),
         Some(TimestampedTracePackets {
             packets: [TracePacket::Overflow].into(),
             malformed_packets: [].into(),
+            raw: vec![],
             timestamp: Timestamp {
                 base: Some((0b1_0010001_1110100_0111101 << 26) | (0b0_0000100_0100000_0000000)),
                 delta: Some(0b1_1001001 * 3),
                 data_relation: Some(TimestampDataRelation::Sync),
                 diverged: true,
             },
+            earliest: None,
+            clock_changed: false,
+            incomplete: false,
             packets_consumed: 2,
+            location: span(24, 28),
         }),
         Some(TimestampedTracePackets {
             packets: [].into(),
             malformed_packets: [].into(),
+            raw: vec![],
             timestamp: Timestamp {
                 base: Some((0b1_0010001_1110100_0111101 << 26) | (0b0_0000100_0100000_0000000)),
                 delta: Some(0b1_1001001),
                 data_relation: Some(TimestampDataRelation::UnknownAssocEventDelay),
                 diverged: false,
             },
+            // Generated as early as the previous local timestamp
+            earliest: Some(Timestamp {
+                base: Some((0b1_0010001_1110100_0111101 << 26) | (0b0_0000100_0100000_0000000)),
+                delta: Some(0b1_1001001 * 3),
+                data_relation: Some(TimestampDataRelation::Sync),
+                diverged: true,
+            }),
+            clock_changed: false,
+            incomplete: false,
             packets_consumed: 3,
+            location: span(28, 41),
         }),
         None,
     ]
@@ -486,28 +666,40 @@ fn pull_with_timestamp_malformed() {
             0b0000_0001,
 
             // Pull!
-        ]);
+        ]).unwrap();
 
-    for set in [Some(TimestampedTracePackets {
-        packets: [
-            TracePacket::PCSample { pc: None },
-            TracePacket::PCSample { pc: None },
-            TracePacket::PCSample { pc: None },
-        ]
-        .into(),
-        malformed_packets: [MalformedPacket::InvalidHardwareDisc {
-            disc_id: 31,
-            size: 3,
-        }]
-        .into(),
-        timestamp: Timestamp {
-            base: Some((0b1_0010001_1110100_0111101 << 26) | (0b0_0000100_0100000_0000000)),
-            delta: Some(0b1_1001001),
-            data_relation: Some(TimestampDataRelation::Sync),
-            diverged: false,
-        },
-        packets_consumed: 7,
-    })]
+    for set in [
+        Some(TimestampedTracePackets {
+            packets: [
+                TracePacket::PCSample { pc: None },
+                TracePacket::PCSample { pc: None },
+                TracePacket::PCSample { pc: None },
+            ]
+            .into(),
+            malformed_packets: [Located {
+                value: MalformedPacket::InvalidHardwareDisc {
+                    disc_id: 31,
+                    size: 3,
+                },
+                location: span(6, 7),
+                raw: None,
+            }]
+            .into(),
+            raw: vec![],
+            timestamp: Timestamp {
+                base: Some((0b1_0010001_1110100_0111101 << 26) | (0b0_0000100_0100000_0000000)),
+                delta: Some(0b1_1001001),
+                data_relation: Some(TimestampDataRelation::Sync),
+                diverged: false,
+            },
+            earliest: None,
+            clock_changed: false,
+            incomplete: false,
+            packets_consumed: 7,
+            location: span(0, 20),
+        }),
+        None,
+    ]
     .iter()
     {
         assert_eq!(decoder.pull_with_timestamp(), *set);
@@ -516,7 +708,10 @@ fn pull_with_timestamp_malformed() {
 
 #[test]
 fn pull_with_timestamp_gts_only() {
-    let mut decoder = Decoder::new(DecoderOptions { only_gts: true });
+    let mut decoder = Decoder::new(DecoderOptions {
+        only_gts: true,
+        ..DecoderOptions::default()
+    });
     #[rustfmt::skip]
         decoder.push(&[
             // PC sample (sleeping)
@@ -553,30 +748,40 @@ fn pull_with_timestamp_gts_only() {
             // Pull!
 
             // Pull!
-        ]);
+        ]).unwrap();
 
     for set in [
         Some(TimestampedTracePackets {
             packets: [TracePacket::PCSample { pc: None }].into(),
             malformed_packets: [].into(),
+            raw: vec![],
             timestamp: Timestamp {
                 base: None,
                 delta: None,
                 data_relation: None,
                 diverged: false,
             },
+            earliest: None,
+            clock_changed: false,
+            incomplete: false,
             packets_consumed: 1,
+            location: span(0, 2),
         }),
         Some(TimestampedTracePackets {
             packets: [TracePacket::PCSample { pc: None }].into(),
             malformed_packets: [].into(),
+            raw: vec![],
             timestamp: Timestamp {
                 base: Some((0b1_0010001_1110100_0111101 << 26) | (0b0_0000100_0100000_0000000)),
                 delta: None,
                 data_relation: None,
                 diverged: false,
             },
+            earliest: None,
+            clock_changed: false,
+            incomplete: false,
             packets_consumed: 1,
+            location: span(12, 14),
         }),
         Some(TimestampedTracePackets {
             packets: [TracePacket::LocalTimestamp1 {
@@ -585,13 +790,18 @@ fn pull_with_timestamp_gts_only() {
             }]
             .into(),
             malformed_packets: [].into(),
+            raw: vec![],
             timestamp: Timestamp {
                 base: Some((0b1_0010001_1110100_0111101 << 26) | (0b0_0000100_0100000_0000000)),
                 delta: None,
                 data_relation: None,
                 diverged: false,
             },
+            earliest: None,
+            clock_changed: false,
+            incomplete: false,
             packets_consumed: 1,
+            location: span(14, 17),
         }),
         None,
     ]
@@ -600,3 +810,1568 @@ fn pull_with_timestamp_gts_only() {
         assert_eq!(decoder.pull_with_timestamp(), *set);
     }
 }
+
+#[test]
+fn packets_iterator() {
+    let mut decoder = Decoder::new(DecoderOptions::default());
+    #[rustfmt::skip]
+    decoder.push(&[
+        // Overflow
+        0b0111_0000,
+
+        // Malformed header
+        0b1111_1111,
+
+        // PC sample (sleeping)
+        0b0001_0101,
+        0b0000_0000,
+
+        // Instrumentation packet, payload not yet received
+        0b0000_1010,
+    ]).unwrap();
+
+    assert_eq!(
+        decoder.packets().collect::<Vec<_>>(),
+        [
+            Ok(TracePacket::Overflow),
+            Err(MalformedPacket::InvalidHardwareDisc {
+                disc_id: 31,
+                size: 3,
+            }),
+            Ok(TracePacket::PCSample { pc: None }),
+        ]
+    );
+
+    decoder.push(b"hi").unwrap();
+    assert_eq!(
+        decoder.packets().filter_map(Result::ok).collect::<Vec<_>>(),
+        [TracePacket::Instrumentation {
+            port: 1,
+            payload: Payload::from_slice(b"hi").unwrap(),
+        }]
+    );
+}
+
+#[test]
+fn timestamped_packets_iterator() {
+    let mut decoder = Decoder::new(DecoderOptions::default());
+    #[rustfmt::skip]
+    decoder.push(&[
+        // PC sample (sleeping)
+        0b0001_0101,
+        0b0000_0000,
+
+        // LTS2
+        0b0011_0000,
+
+        // Overflow
+        0b0111_0000,
+
+        // LTS2
+        0b0101_0000,
+
+        // PC sample (sleeping), never timestamped
+        0b0001_0101,
+        0b0000_0000,
+    ]).unwrap();
+
+    assert_eq!(
+        decoder
+            .timestamped_packets()
+            .map(|set| (set.packets, set.timestamp.delta))
+            .collect::<Vec<_>>(),
+        [
+            ([TracePacket::PCSample { pc: None }].to_vec(), Some(3)),
+            ([TracePacket::Overflow].to_vec(), Some(8)),
+        ]
+    );
+}
+
+#[test]
+fn pull_located() {
+    let mut decoder = Decoder::new(DecoderOptions::default());
+    #[rustfmt::skip]
+    decoder.push(&[
+        // Overflow
+        0b0111_0000,
+
+        // Sync (50 zeros and a set bit), followed by a malformed
+        // header (0b1111_1111) misaligned by three bits
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b1111_1100,
+        0b0000_0111,
+    ]).unwrap();
+
+    let at = |byte, bit| StreamPosition { byte, bit };
+    assert_eq!(
+        decoder.pull_located(),
+        Ok(Some(Located {
+            value: TracePacket::Overflow,
+            location: span(0, 1),
+            raw: None,
+        }))
+    );
+    assert_eq!(
+        decoder.pull_located(),
+        Ok(Some(Located {
+            value: TracePacket::Sync,
+            location: Location {
+                start: at(1, 0),
+                end: at(7, 3),
+            },
+            raw: None,
+        }))
+    );
+
+    let malformed = decoder.pull_located().unwrap_err();
+    assert_eq!(
+        malformed,
+        Located {
+            value: MalformedPacket::InvalidHardwareDisc {
+                disc_id: 31,
+                size: 3,
+            },
+            location: Location {
+                start: at(7, 3),
+                end: at(8, 3),
+            },
+            raw: None,
+        }
+    );
+    assert_eq!(
+        malformed.to_string(),
+        "Hardware source packet discriminator ID is invalid: 31 (at byte 7 bit 3 to byte 8 bit 3)"
+    );
+    assert_eq!(decoder.pull_located(), Ok(None));
+    assert_eq!(decoder.position(), at(8, 3));
+}
+
+#[test]
+fn recover_skip_to_sync() {
+    let mut decoder = Decoder::new(DecoderOptions {
+        recovery: RecoveryPolicy::SkipToSync,
+        ..DecoderOptions::default()
+    });
+    #[rustfmt::skip]
+    decoder.push(&[
+        // Malformed header
+        0b1111_1111,
+
+        // Instrumentation packet, discarded
+        0b0000_1010,
+        b'h',
+        b'i',
+
+        // Sync (48 zeros, one of which in the byte above, and a set bit)
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b1000_0000,
+
+        // Overflow
+        0b0111_0000,
+    ]).unwrap();
+
+    assert!(decoder.pull().is_err());
+    assert_eq!(decoder.pull(), Ok(Some(TracePacket::Sync)));
+    assert_eq!(decoder.pull(), Ok(Some(TracePacket::Overflow)));
+    assert_eq!(decoder.pull(), Ok(None));
+    assert_eq!(decoder.bytes_discarded(), 3);
+}
+
+#[test]
+fn recover_heuristic() {
+    let mut decoder = Decoder::new(DecoderOptions {
+        recovery: RecoveryPolicy::Heuristic,
+        ..DecoderOptions::default()
+    });
+
+    // Two malformed headers, followed by instrumentation packets
+    let instr = [0b0000_1001, b'x'];
+    decoder.push(&[0b1111_1111, 0b1111_1111]).unwrap();
+    decoder.push(&instr.repeat(10)).unwrap();
+
+    assert!(decoder.pull().is_err());
+    // Not enough data to score alignments yet
+    assert_eq!(decoder.pull(), Ok(None));
+
+    decoder.push(&instr.repeat(6)).unwrap();
+    for _ in 0..16 {
+        assert_eq!(
+            decoder.pull(),
+            Ok(Some(TracePacket::Instrumentation {
+                port: 1,
+                payload: Payload::from_slice(b"x").unwrap(),
+            }))
+        );
+    }
+    assert_eq!(decoder.pull(), Ok(None));
+    assert_eq!(decoder.bytes_discarded(), 1);
+}
+
+#[test]
+fn recover_heuristic_finish() {
+    let mut decoder = Decoder::new(DecoderOptions {
+        recovery: RecoveryPolicy::Heuristic,
+        ..DecoderOptions::default()
+    });
+
+    // A malformed header followed by fewer bytes than the lookahead
+    decoder.push(&[0b1111_1111]).unwrap();
+    decoder.push(&[0b0111_0000; 10]).unwrap();
+    assert_eq!(decoder.packets().count(), 1);
+    assert_eq!(decoder.buffered_len(), 10);
+
+    // The remaining data is scored at the end of the input
+    decoder.finish();
+    assert!(decoder.is_finished());
+    assert_eq!(
+        decoder.packets().collect::<Vec<_>>(),
+        vec![Ok(TracePacket::Overflow); 10]
+    );
+    assert_eq!(decoder.buffered_len(), 0);
+    assert_eq!(decoder.bytes_discarded(), 0);
+
+    // Data that decodes from no offset is resumed at naively
+    decoder.push(&[0b1111_1111, 0b1111_1111]).unwrap();
+    assert!(!decoder.is_finished());
+    assert!(decoder.pull().is_err());
+    assert_eq!(decoder.pull(), Ok(None));
+    decoder.finish();
+    assert!(decoder.pull().is_err());
+    assert_eq!(decoder.pull(), Ok(None));
+    assert_eq!(decoder.buffered_len(), 0);
+}
+
+#[test]
+fn retain_raw() {
+    let mut decoder = Decoder::new(DecoderOptions {
+        retain_raw: true,
+        ..DecoderOptions::default()
+    });
+    #[rustfmt::skip]
+    decoder.push(&[
+        // Sync (50 zeros and a set bit), followed by a malformed
+        // header (0b1111_1111) misaligned by three bits
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b1111_1100,
+        0b0100_1111,
+
+        // Instrumentation packet, misaligned by three bits
+        0b0100_0000,
+        0b0000_0011,
+    ]).unwrap();
+
+    let raw = |sync_zeros, bytes: &[u8]| {
+        Some(RawPacket {
+            sync_zeros,
+            bytes: bytes.to_vec(),
+        })
+    };
+    assert_eq!(
+        decoder.pull_located().unwrap().unwrap().raw,
+        raw(Some(50), &[])
+    );
+    assert_eq!(
+        decoder.pull_located().unwrap_err().raw,
+        raw(None, &[0b1111_1111])
+    );
+    assert_eq!(
+        decoder.pull_located().unwrap().unwrap(),
+        Located {
+            value: TracePacket::Instrumentation {
+                port: 1,
+                payload: Payload::from_slice(b"h").unwrap(),
+            },
+            location: Location {
+                start: StreamPosition { byte: 7, bit: 3 },
+                end: StreamPosition { byte: 9, bit: 3 },
+            },
+            raw: raw(None, &[0b0000_1001, b'h']),
+        }
+    );
+
+    // Also for the packets of timestamped sets
+    let mut decoder = Decoder::new(DecoderOptions {
+        retain_raw: true,
+        ..DecoderOptions::default()
+    });
+    #[rustfmt::skip]
+    decoder.push(&[
+        // Instrumentation packet
+        0b0000_1001,
+        b'h',
+
+        // Overflow
+        0b0111_0000,
+
+        // Malformed header
+        0b1111_1111,
+    ]).unwrap();
+    decoder.finish();
+    assert_eq!(decoder.pull_with_timestamp(), None);
+    let set = decoder.flush_timestamped().unwrap();
+    assert_eq!(
+        set.raw.into_iter().map(Some).collect::<Vec<_>>(),
+        [raw(None, &[0b0000_1001, b'h']), raw(None, &[0b0111_0000])]
+    );
+    assert_eq!(set.malformed_packets[0].raw, raw(None, &[0b1111_1111]));
+}
+
+#[test]
+fn snapshot_restore() {
+    // A Synchronization packet of 50 zeros, misaligning the packets
+    // that follow by three bits
+    let mut encoder = Encoder::new(EncoderOptions::default());
+    let mut bits = vec![false; 50];
+    bits.push(true);
+    for packet in [
+        TracePacket::Overflow,
+        TracePacket::PCSample { pc: None },
+        TracePacket::GlobalTimestamp1 {
+            ts: 0b10_1010,
+            wrap: false,
+            clkch: false,
+        },
+        TracePacket::GlobalTimestamp2 { ts: 0b11 },
+        TracePacket::LocalTimestamp1 {
+            ts: 0b1_1001001,
+            data_relation: TimestampDataRelation::Sync,
+        },
+        TracePacket::PCSample { pc: None },
+    ] {
+        for b in encoder.encode(&packet).unwrap() {
+            bits.extend((0..8).map(|i| b & (1 << i) != 0));
+        }
+    }
+    let trace_data: Vec<u8> = bits
+        .chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |b, (i, bit)| b | (*bit as u8) << i)
+        })
+        .collect();
+
+    let decode = |decoder: &mut Decoder| decoder.timestamped_packets().collect::<Vec<_>>();
+    let mut expected = Decoder::new(DecoderOptions::default());
+    expected.push(&trace_data).unwrap();
+    let expected = decode(&mut expected);
+    assert_eq!(expected.len(), 1);
+    assert_eq!(expected[0].packets.len(), 3);
+
+    for split in 0..trace_data.len() {
+        let mut decoder = Decoder::new(DecoderOptions::default());
+        decoder.push(&trace_data[..split]).unwrap();
+        let mut sets = decode(&mut decoder);
+
+        let snapshot = decoder.snapshot();
+        #[cfg(feature = "serde")]
+        let snapshot = serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+        drop(decoder);
+
+        let mut decoder = Decoder::restore(snapshot);
+        decoder.push(&trace_data[split..]).unwrap();
+        sets.extend(decode(&mut decoder));
+        assert_eq!(sets, expected, "split at byte {}", split);
+    }
+}
+
+#[test]
+fn bounded_buffer() {
+    let mut decoder = Decoder::new(DecoderOptions {
+        capacity: Some(3),
+        ..DecoderOptions::default()
+    });
+    assert_eq!(decoder.remaining_capacity(), Some(3));
+    assert_eq!(decoder.push(&[0b0000_1010, b'h', b'i', 0b0111_0000]), Ok(3));
+    assert_eq!(decoder.buffered_len(), 3);
+    assert_eq!(decoder.remaining_capacity(), Some(0));
+    assert_eq!(decoder.push(&[0b0111_0000]), Err(BufferFull));
+    assert_eq!(decoder.push(&[]), Ok(0));
+
+    assert_eq!(
+        decoder.pull(),
+        Ok(Some(TracePacket::Instrumentation {
+            port: 1,
+            payload: Payload::from_slice(b"hi").unwrap(),
+        }))
+    );
+    assert_eq!(decoder.remaining_capacity(), Some(3));
+    assert_eq!(decoder.push(&[0b0111_0000]), Ok(1));
+    assert_eq!(decoder.pull(), Ok(Some(TracePacket::Overflow)));
+    assert_eq!(decoder.buffered_len(), 0);
+}
+
+#[test]
+fn bounded_timestamp_payload() {
+    // An LTS1 header followed by bytes that all set the continuation-bit
+    let mut trace_data = vec![0b1100_0000];
+    trace_data.extend_from_slice(&[0xFF; 40]);
+
+    let mut decoder = Decoder::new(DecoderOptions {
+        capacity: Some(16),
+        ..DecoderOptions::default()
+    });
+    let mut data = &trace_data[..];
+    let mut malformed = vec![];
+    while !data.is_empty() || decoder.buffered_len() > 0 {
+        let len = decoder.push(data).unwrap();
+        data = &data[len..];
+        match decoder.pull() {
+            Err(e) => malformed.push(e),
+            Ok(None) => assert!(len > 0, "decoder stalled"),
+            Ok(Some(_)) => (),
+        }
+    }
+    assert_eq!(
+        malformed[0],
+        MalformedPacket::InvalidTimestampSize {
+            kind: PacketKind::LocalTimestamp1,
+            payload: Payload::from_slice(&[0xFF; 4]).unwrap(),
+        }
+    );
+
+    // The payload of a GTS2 ends within six bytes
+    let mut decoder = Decoder::new(DecoderOptions::default());
+    decoder.push(&[0b1011_0100]).unwrap();
+    decoder.push(&[0xFF; 7]).unwrap();
+    assert_eq!(
+        decoder.pull(),
+        Err(MalformedPacket::InvalidGTS2Size {
+            payload: Payload::from_slice(&[0xFF; 6]).unwrap(),
+        })
+    );
+}
+
+/// Prefixes `data` with a Synchronization packet of 51 zeros, which
+/// leaves the bitstream misaligned by four bits.
+fn misaligned(data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0; 6];
+    let mut carry = 0b1000;
+    for b in data {
+        bytes.push(carry | (b << 4));
+        carry = b >> 4;
+    }
+    bytes.push(carry);
+    bytes
+}
+
+/// Decodes `data` with a decoder of the given options, pushing as much
+/// as the decoder accepts between pulls. Panics if the decoder stalls.
+fn decode_bounded(options: DecoderOptions, mut data: &[u8]) -> Vec<TracePacket> {
+    let mut decoder = Decoder::new(options);
+    let mut packets = vec![];
+    loop {
+        // Fails with a full buffer
+        let len = decoder.push(data).unwrap_or(0);
+        data = &data[len..];
+        match decoder.pull() {
+            Ok(Some(packet)) => packets.push(packet),
+            Ok(None) if data.is_empty() => return packets,
+            Ok(None) => assert!(len > 0, "decoder stalled"),
+            Err(_) => (),
+        }
+    }
+}
+
+#[test]
+fn bounded_buffer_misaligned() {
+    let gts2 = TracePacket::GlobalTimestamp2 { ts: (1 << 38) - 1 };
+    let mut encoder = Encoder::new(EncoderOptions {
+        gts2_width: GlobalTimestampWidth::Bits64,
+        ..EncoderOptions::default()
+    });
+    let trace_data = misaligned(&encoder.encode(&gts2).unwrap());
+
+    // Six payload bytes span seven buffered bytes
+    assert_eq!(
+        DecoderOptions::builder().capacity(6).build(),
+        Err(InvalidDecoderOptions::Capacity(6))
+    );
+    let options = DecoderOptions::builder().capacity(7).build().unwrap();
+    assert_eq!(
+        decode_bounded(options, &trace_data),
+        [TracePacket::Sync, gts2]
+    );
+
+    // The lookahead of the heuristic recovery spans 33 buffered bytes
+    let mut packets = vec![0b1111_1111];
+    packets.extend_from_slice(&[0b0111_0000; 40]);
+    let trace_data = misaligned(&packets);
+    let options = |capacity| {
+        DecoderOptions::builder()
+            .recovery(RecoveryPolicy::Heuristic)
+            .capacity(capacity)
+            .build()
+    };
+    assert_eq!(options(32), Err(InvalidDecoderOptions::Capacity(32)));
+    let packets = decode_bounded(options(33).unwrap(), &trace_data);
+    assert_eq!(packets[0], TracePacket::Sync);
+    assert!(packets[1..].iter().all(|p| p == &TracePacket::Overflow));
+    assert!(packets.len() > 1);
+}
+
+#[test]
+fn options_builder() {
+    assert_eq!(
+        DecoderOptions::builder().build(),
+        Ok(DecoderOptions::default())
+    );
+    assert_eq!(
+        DecoderOptions::builder().stimulus_ports(257).build(),
+        Err(InvalidDecoderOptions::StimulusPorts(257))
+    );
+    assert_eq!(
+        DecoderOptions::builder().comparators(5).build(),
+        Err(InvalidDecoderOptions::Comparators(5))
+    );
+    assert_eq!(
+        DecoderOptions::builder().sync_min_zeros(7).build(),
+        Err(InvalidDecoderOptions::SyncMinZeros(7))
+    );
+    assert_eq!(
+        DecoderOptions::builder()
+            .recovery(RecoveryPolicy::Heuristic)
+            .capacity(16)
+            .build(),
+        Err(InvalidDecoderOptions::Capacity(16))
+    );
+}
+
+#[test]
+fn validate_target_configuration() {
+    let options = DecoderOptions::builder()
+        .gts2_width(GlobalTimestampWidth::Bits64)
+        .stimulus_ports(64)
+        .comparators(2)
+        .sync_min_zeros(63)
+        .build()
+        .unwrap();
+    let mut decoder = Decoder::new(options);
+    #[rustfmt::skip]
+    decoder.push(&[
+        // Sync (47 zeros)
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b1000_0000,
+
+        // Instrumentation (port 1)
+        0b0000_1001,
+        b'h',
+
+        // Extension (page 2), Instrumentation (port 64 + 1)
+        0b0010_1000,
+        0b0000_1001,
+        b'h',
+
+        // DWT data trace value (comparator 2)
+        0b1010_1101,
+        0b0000_0011,
+
+        // GTS2 (48-bit)
+        0b1011_0100,
+        0b1011_1101,
+        0b1111_0100,
+        0b1001_0001,
+        0b0000_0001,
+    ]).unwrap();
+
+    assert_eq!(decoder.pull(), Err(MalformedPacket::InvalidSync(47)));
+    assert_eq!(
+        decoder.pull(),
+        Ok(Some(TracePacket::Instrumentation {
+            port: 1,
+            payload: Payload::from_slice(b"h").unwrap(),
+        }))
+    );
+    assert_eq!(decoder.pull(), Ok(Some(TracePacket::Extension { page: 2 })));
+    assert_eq!(
+        decoder.pull(),
+        Err(MalformedPacket::InvalidStimulusPort(65))
+    );
+    assert_eq!(decoder.pull(), Err(MalformedPacket::InvalidComparator(2)));
+    assert!(matches!(
+        decoder.pull(),
+        Err(MalformedPacket::InvalidGTS2Size { .. })
+    ));
+}
+
+#[test]
+fn lts_prescaler() {
+    let mut decoder = Decoder::new(
+        DecoderOptions::builder()
+            .lts_prescaler(LocalTimestampPrescaler::Div16)
+            .build()
+            .unwrap(),
+    );
+    #[rustfmt::skip]
+    decoder.push(&[
+        // LTS2
+        0b0011_0000,
+
+        // LTS1
+        0b1100_0000,
+        0b1100_1001,
+        0b0000_0001,
+    ]).unwrap();
+
+    assert_eq!(
+        decoder.pull_with_timestamp().unwrap().timestamp.delta,
+        Some(3 * 16)
+    );
+    assert_eq!(
+        decoder.pull_with_timestamp().unwrap().timestamp.delta,
+        Some((3 + 0b1_1001001) * 16)
+    );
+
+    // Scaled and summed deltas saturate rather than overflow usize
+    let mut decoder = Decoder::new(
+        DecoderOptions::builder()
+            .lts_prescaler(LocalTimestampPrescaler::Div64)
+            .build()
+            .unwrap(),
+    );
+    let lts1 = [0b1100_0000, 0xFF, 0xFF, 0xFF, 0x7F];
+    decoder.push(&lts1).unwrap();
+    decoder.push(&lts1).unwrap();
+    let max = ((1usize << 28) - 1).saturating_mul(64);
+    assert_eq!(
+        decoder.pull_with_timestamp().unwrap().timestamp.delta,
+        Some(max)
+    );
+    assert_eq!(
+        decoder.pull_with_timestamp().unwrap().timestamp.delta,
+        Some(max.saturating_add(max))
+    );
+}
+
+#[test]
+fn validation_modes() {
+    #[rustfmt::skip]
+    let trace_data = [
+        // Event counter wrap (CPI), reserved bits set
+        0b0000_0101,
+        0b1100_0001,
+
+        // PC sleep, reserved bit set
+        0b0001_0101,
+        0b0000_0001,
+
+        // Data trace address (comparator 0), reserved 4-byte payload
+        0b0100_1111,
+        0x34,
+        0x12,
+        0xAB,
+        0xCD,
+    ];
+    let violations = [
+        MalformedPacket::ReservedBits {
+            disc_id: 0,
+            payload: Payload::from_slice(&[0b1100_0001]).unwrap(),
+        },
+        MalformedPacket::ReservedBits {
+            disc_id: 2,
+            payload: Payload::from_slice(&[0b0000_0001]).unwrap(),
+        },
+        MalformedPacket::ReservedEncoding {
+            disc_id: 9,
+            payload: Payload::from_slice(&[0x34, 0x12, 0xAB, 0xCD]).unwrap(),
+        },
+    ];
+
+    let options = DecoderOptions::builder()
+        .validation(ValidationMode::Strict)
+        .build()
+        .unwrap();
+    let mut decoder = Decoder::new(options.clone());
+    decoder.push(&trace_data).unwrap();
+    for violation in violations.iter() {
+        assert_eq!(decoder.pull(), Err(violation.clone()));
+    }
+    assert_eq!(decoder.pull(), Ok(None));
+    assert!(decoder.take_warnings().is_empty());
+
+    // Lenient by default
+    assert_eq!(
+        DecoderOptions::default().validation,
+        ValidationMode::Lenient
+    );
+    let mut decoder = Decoder::new(DecoderOptions::default());
+    decoder.push(&trace_data).unwrap();
+    for packet in [
+        TracePacket::EventCounterWrap {
+            cyc: false,
+            fold: false,
+            lsu: false,
+            sleep: false,
+            exc: false,
+            cpi: true,
+        },
+        TracePacket::PCSample { pc: None },
+        TracePacket::DataTraceAddress {
+            comparator: 0,
+            data: Payload::from_slice(&[0x34, 0x12]).unwrap(),
+        },
+    ] {
+        assert_eq!(decoder.pull(), Ok(Some(packet)));
+    }
+    assert_eq!(decoder.pull(), Ok(None));
+
+    let warnings: Vec<_> = violations
+        .iter()
+        .cloned()
+        .zip([span(0, 2), span(2, 4), span(4, 9)])
+        .map(|(value, location)| Located {
+            value,
+            location,
+            raw: None,
+        })
+        .collect();
+    assert_eq!(decoder.take_warnings(), warnings);
+    assert!(decoder.take_warnings().is_empty());
+
+    // Only the most recent warnings are retained, but all are counted
+    for _ in 0..20 {
+        decoder.push(&trace_data).unwrap();
+    }
+    assert_eq!(decoder.packets().count(), 60);
+    let warnings = decoder.take_warnings();
+    assert_eq!(warnings.len(), 32);
+    assert_eq!(warnings.last().unwrap().location, span(184, 189));
+    assert_eq!(decoder.stats().warnings, 63);
+
+    // Packets rejected before validation modes existed are only
+    // rejected in strict mode
+    #[rustfmt::skip]
+    let trace_data = [
+        // Data trace PC value (comparator 0), one byte
+        0b0100_0101,
+        0x12,
+
+        // PC sample, one non-zero byte
+        0b0001_0101,
+        0b0000_0010,
+    ];
+    let mut decoder = Decoder::new(options);
+    decoder.push(&trace_data).unwrap();
+    assert_eq!(
+        decoder.pull(),
+        Err(MalformedPacket::ReservedEncoding {
+            disc_id: 8,
+            payload: Payload::from_slice(&[0x12]).unwrap(),
+        })
+    );
+    assert_eq!(
+        decoder.pull(),
+        Err(MalformedPacket::ReservedBits {
+            disc_id: 2,
+            payload: Payload::from_slice(&[0b0000_0010]).unwrap(),
+        })
+    );
+    let mut lenient = Decoder::new(DecoderOptions::default());
+    lenient.push(&trace_data).unwrap();
+    assert_eq!(
+        lenient.packets().collect::<Vec<_>>(),
+        [
+            Ok(TracePacket::DataTracePC {
+                comparator: 0,
+                pc: 0x12,
+            }),
+            Ok(TracePacket::PCSample { pc: None }),
+        ]
+    );
+    assert_eq!(lenient.take_warnings().len(), 2);
+}
+
+#[test]
+fn filter_packets() {
+    #[rustfmt::skip]
+    let trace_data = [
+        // Instrumentation (port 1)
+        0b0000_1001,
+        b'a',
+
+        // Extension (page 1), Instrumentation (port 32 + 1)
+        0b0001_1000,
+        0b0000_1001,
+        b'b',
+
+        // PC sample (sleeping)
+        0b0001_0101,
+        0b0000_0000,
+
+        // Exception trace (SysTick entered)
+        0b0000_1110,
+        0b0000_1111,
+        0b0001_0000,
+
+        // GTS1
+        0b1001_0100,
+        0b1000_0000,
+        0b1010_0000,
+        0b1000_0100,
+        0b0000_0000,
+
+        // LTS1
+        0b1100_0000,
+        0b1100_1001,
+        0b0000_0001,
+    ];
+    let expected = [
+        (
+            TracePacket::Instrumentation {
+                port: 33,
+                payload: Payload::from_slice(b"b").unwrap(),
+            },
+            span(3, 5),
+        ),
+        (
+            TracePacket::ExceptionTrace {
+                exception: ExceptionType::SysTick,
+                action: ExceptionAction::Entered,
+            },
+            span(7, 10),
+        ),
+        (
+            TracePacket::LocalTimestamp1 {
+                ts: 201,
+                data_relation: TimestampDataRelation::Sync,
+            },
+            span(15, 18),
+        ),
+    ];
+    let filter = PacketFilter::none()
+        .with_kind(PacketKind::ExceptionTrace)
+        .with_kind(PacketKind::LocalTimestamp1)
+        .with_port(33);
+    assert!(!filter.matches_port(1));
+    assert!(!filter.matches_kind(PacketKind::Extension));
+    let options = DecoderOptions::builder()
+        .filter(filter)
+        .retain_raw(true)
+        .build()
+        .unwrap();
+
+    // Payloads of skipped packets may be split across pushes
+    for chunk in [trace_data.len(), 1] {
+        let mut decoder = Decoder::new(options.clone());
+        let mut packets = vec![];
+        for data in trace_data.chunks(chunk) {
+            decoder.push(data).unwrap();
+            while let Some(packet) = decoder.pull_located().unwrap() {
+                packets.push(packet);
+            }
+        }
+
+        assert_eq!(packets.len(), expected.len());
+        for (packet, (value, location)) in packets.into_iter().zip(expected.iter()) {
+            assert_eq!(&packet.value, value);
+            assert_eq!(&packet.location, location);
+            assert_eq!(
+                packet.raw.unwrap().bytes,
+                trace_data[location.start.byte as usize..location.end.byte as usize]
+            );
+        }
+    }
+
+    let mut decoder = Decoder::new(options);
+    decoder.push(&trace_data).unwrap();
+    assert_eq!(
+        decoder.pull_with_timestamp(),
+        Some(TimestampedTracePackets {
+            timestamp: Timestamp {
+                base: None,
+                delta: Some(201),
+                data_relation: Some(TimestampDataRelation::Sync),
+                diverged: false,
+            },
+            earliest: None,
+            packets: vec![expected[0].0.clone(), expected[1].0.clone()],
+            malformed_packets: vec![],
+            raw: expected[..2]
+                .iter()
+                .map(|(_, location)| RawPacket {
+                    sync_zeros: None,
+                    bytes: trace_data[location.start.byte as usize..location.end.byte as usize]
+                        .to_vec(),
+                })
+                .collect(),
+            clock_changed: false,
+            incomplete: false,
+            packets_consumed: 7,
+            location: span(3, 18),
+        })
+    );
+}
+
+#[test]
+fn filter_timestamps() {
+    let exception = TracePacket::ExceptionTrace {
+        exception: ExceptionType::SysTick,
+        action: ExceptionAction::Entered,
+    };
+    let mut encoder = Encoder::new(EncoderOptions::default());
+    let mut decoder = Decoder::new(DecoderOptions {
+        filter: PacketFilter::none().with_kind(PacketKind::ExceptionTrace),
+        ..DecoderOptions::default()
+    });
+    for packet in [
+        TracePacket::GlobalTimestamp1 {
+            ts: 1000,
+            wrap: true,
+            clkch: false,
+        },
+        TracePacket::GlobalTimestamp2 { ts: 1 },
+        TracePacket::PCSample { pc: None },
+        exception.clone(),
+        TracePacket::Overflow,
+        TracePacket::LocalTimestamp1 {
+            ts: 201,
+            data_relation: TimestampDataRelation::Sync,
+        },
+    ] {
+        decoder.push(&encoder.encode(&packet).unwrap()).unwrap();
+    }
+
+    // Timestamp packets are tracked although they do not match
+    let set = decoder.pull_with_timestamp().unwrap();
+    assert_eq!(set.packets, [exception]);
+    assert_eq!(
+        set.timestamp,
+        Timestamp {
+            base: Some((1 << 26) | 1000),
+            delta: Some(201),
+            data_relation: Some(TimestampDataRelation::Sync),
+            diverged: true,
+        }
+    );
+    assert_eq!(decoder.pull_with_timestamp(), None);
+    assert_eq!(decoder.flush_timestamped(), None);
+    assert_eq!(decoder.stats().total_packets(), 6);
+}
+
+#[test]
+fn decoder_stats() {
+    let options = DecoderOptions::builder()
+        .recovery(RecoveryPolicy::SkipToSync)
+        .filter(PacketFilter::all().without_kind(PacketKind::PCSample))
+        .build()
+        .unwrap();
+    let mut decoder = Decoder::new(options);
+    #[rustfmt::skip]
+    decoder.push(&[
+        // Overflow
+        0b0111_0000,
+
+        // PC sample (sleeping), not returned
+        0b0001_0101,
+        0b0000_0000,
+
+        // Invalid hardware source discriminator, followed by a
+        // discarded byte
+        0b1111_0100,
+        0b0111_0000,
+
+        // Sync (47 zeros)
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b0000_0000,
+        0b1000_0000,
+
+        // Overflow
+        0b0111_0000,
+    ]).unwrap();
+
+    let results: Vec<_> = decoder.packets().collect();
+    assert_eq!(
+        results,
+        [
+            Ok(TracePacket::Overflow),
+            Err(MalformedPacket::InvalidHardwareDisc {
+                disc_id: 30,
+                size: 0
+            }),
+            Ok(TracePacket::Sync),
+            Ok(TracePacket::Overflow),
+        ]
+    );
+
+    let stats = decoder.stats();
+    assert_eq!(stats.overflows(), 2);
+    assert_eq!(stats.syncs(), 1);
+    assert_eq!(stats.packets(PacketKind::PCSample), 1);
+    assert_eq!(stats.total_packets(), 4);
+    assert_eq!(stats.malformed(MalformedKind::InvalidHardwareDisc), 1);
+    assert_eq!(stats.total_malformed(), 1);
+    assert_eq!(stats.bytes_consumed, 12);
+    assert_eq!(stats.bytes_discarded, 1);
+    assert_eq!(stats.warnings, 0);
+
+    decoder.reset_stats();
+    assert_eq!(decoder.stats(), &DecoderStats::default());
+    // Read from the same counter
+    assert_eq!(decoder.bytes_discarded(), 0);
+}
+
+#[test]
+fn display() {
+    let packets = [
+        (
+            TracePacket::Instrumentation {
+                port: 0,
+                payload: Payload::from_slice(b"hi").unwrap(),
+            },
+            "Instrumentation, port 0: 0x6968",
+            "Instrumentation, port 0: 0x6968 (26984, bytes [68 69])",
+        ),
+        (
+            TracePacket::ExceptionTrace {
+                exception: ExceptionType::Interrupt { irqn: 5 },
+                action: ExceptionAction::Returned,
+            },
+            "Exception IRQ5 returned",
+            "Exception IRQ5 (number 21) returned",
+        ),
+        (
+            TracePacket::EventCounterWrap {
+                cyc: true,
+                fold: false,
+                lsu: false,
+                sleep: true,
+                exc: false,
+                cpi: false,
+            },
+            "Event counter wrap: CYC SLEEP",
+            "Event counter wrap: +CYC -FOLD -LSU +SLEEP -EXC -CPI",
+        ),
+        (
+            TracePacket::PCSample {
+                pc: Some(0x0800_0100),
+            },
+            "PC sample 0x08000100",
+            "PC sample 0x08000100",
+        ),
+        (
+            TracePacket::DataTraceValue {
+                comparator: 1,
+                access_type: MemoryAccessType::Write,
+                access_size: AccessSize::Halfword,
+                value: Payload::from_slice(&[0x2a, 0]).unwrap(),
+            },
+            "Data trace, comparator 1: write 0x002a",
+            "Data trace, comparator 1: write Halfword 0x002a (42, bytes [2a 00])",
+        ),
+        (
+            TracePacket::GlobalTimestamp1 {
+                ts: 0x1000,
+                wrap: true,
+                clkch: false,
+            },
+            "Global timestamp lower bits 0x1000, wrap",
+            "Global timestamp lower bits 0x1000 (wrap: true, clock change: false)",
+        ),
+    ];
+    for (packet, display, alternate) in packets.iter() {
+        assert_eq!(&format!("{}", packet), display);
+        assert_eq!(&format!("{:#}", packet), alternate);
+    }
+
+    let malformed = MalformedPacket::ReservedBits {
+        disc_id: 0,
+        payload: Payload::from_slice(&[0xc1]).unwrap(),
+    };
+    assert_eq!(
+        format!("{:#}", malformed),
+        format!("{} (discriminator ID 0, payload [c1])", malformed)
+    );
+
+    let set = TimestampedTracePackets {
+        timestamp: Timestamp {
+            base: Some(1000),
+            delta: Some(201),
+            data_relation: Some(TimestampDataRelation::Sync),
+            diverged: false,
+        },
+        earliest: None,
+        packets: vec![TracePacket::Overflow, TracePacket::PCSample { pc: None }],
+        malformed_packets: vec![Located {
+            value: MalformedPacket::InvalidHeader(0xff),
+            location: span(2, 3),
+            raw: None,
+        }],
+        raw: vec![],
+        clock_changed: false,
+        incomplete: false,
+        packets_consumed: 4,
+        location: span(0, 6),
+    };
+    assert_eq!(
+        format!("{}", set),
+        "[1201] Overflow; PC sample, sleeping; \
+         Error: Header is invalid and cannot be decoded: 0b11111111 (at byte 2 to byte 3)"
+    );
+    assert_eq!(
+        format!("{:#}", set),
+        "[1201 (base 1000, delta 201, synchronous)] 4 packets at byte 0 to byte 6\n  \
+         Overflow\n  \
+         PC sample, sleeping\n  \
+         Error: Header is invalid and cannot be decoded: 0b11111111 (at byte 2 to byte 3)"
+    );
+}
+
+#[test]
+fn flush_timestamped() {
+    let mut decoder = Decoder::new(DecoderOptions::default());
+    #[rustfmt::skip]
+    decoder.push(&[
+        // LTS1
+        0b1100_0000,
+        0b1100_1001,
+        0b0000_0001,
+
+        // PC sample (sleeping)
+        0b0001_0101,
+        0b0000_0000,
+
+        // Malformed header
+        0b1111_1111,
+
+        // First byte of a PC sample
+        0b0001_0111,
+    ]).unwrap();
+
+    assert!(decoder.pull_with_timestamp().is_some());
+    assert_eq!(decoder.pull_with_timestamp(), None);
+    let set = decoder.flush_timestamped().unwrap();
+    assert_eq!(
+        set,
+        TimestampedTracePackets {
+            timestamp: Timestamp {
+                base: None,
+                delta: Some(0b1_1001001),
+                data_relation: None,
+                diverged: false,
+            },
+            earliest: None,
+            packets: vec![TracePacket::PCSample { pc: None }],
+            malformed_packets: vec![Located {
+                value: MalformedPacket::InvalidHardwareDisc {
+                    disc_id: 31,
+                    size: 3,
+                },
+                location: span(5, 6),
+                raw: None,
+            }],
+            raw: vec![],
+            clock_changed: false,
+            incomplete: true,
+            // Including the header of the partial PC sample
+            packets_consumed: 3,
+            location: span(3, 6),
+        }
+    );
+    assert_eq!(set.to_string().split(']').next(), Some("[+201 .."));
+    assert_eq!(decoder.flush_timestamped(), None);
+}
+
+#[test]
+fn flush_timestamped_recovering() {
+    let mut decoder = Decoder::new(DecoderOptions {
+        recovery: RecoveryPolicy::Heuristic,
+        ..DecoderOptions::default()
+    });
+    #[rustfmt::skip]
+    decoder.push(&[
+        // Instrumentation packet
+        0b0000_1010,
+        b'h',
+        b'i',
+
+        // Malformed header
+        0b1111_1111,
+
+        // Instrumentation packet
+        0b0000_1010,
+        b'c',
+        b'r',
+
+        // PC sample (sleeping)
+        0b0001_0101,
+        0b0000_0000,
+    ]).unwrap();
+
+    assert_eq!(decoder.pull_with_timestamp(), None);
+    assert_eq!(decoder.buffered_len(), 5);
+
+    // Recovery finishes on the data that remains
+    decoder.finish();
+    assert_eq!(decoder.pull_with_timestamp(), None);
+    let set = decoder.flush_timestamped().unwrap();
+    assert_eq!(
+        set.packets,
+        [
+            TracePacket::Instrumentation {
+                port: 1,
+                payload: Payload::from_slice(b"hi").unwrap(),
+            },
+            TracePacket::Instrumentation {
+                port: 1,
+                payload: Payload::from_slice(b"cr").unwrap(),
+            },
+            TracePacket::PCSample { pc: None },
+        ]
+    );
+    assert_eq!(set.malformed_packets.len(), 1);
+    assert_eq!(set.packets_consumed, 4);
+    assert_eq!(decoder.buffered_len(), 0);
+    assert_eq!(decoder.flush_timestamped(), None);
+}
+
+#[test]
+fn timing() {
+    use core::num::NonZeroU32;
+    use core::time::Duration;
+
+    let timing = TimingConfig {
+        trace_clock_hz: NonZeroU32::new(48_000_000).unwrap(),
+        lts_prescaler: LocalTimestampPrescaler::Div4,
+        gts_clock_hz: NonZeroU32::new(1_000_000).unwrap(),
+    };
+    let options = DecoderOptions::builder()
+        .lts_prescaler(LocalTimestampPrescaler::Div4)
+        .build()
+        .unwrap();
+    assert_eq!(
+        TimingConfig::new(&options, timing.trace_clock_hz, timing.gts_clock_hz),
+        timing
+    );
+    let mut timestamp = Timestamp {
+        base: Some(1000),
+        delta: Some(480),
+        data_relation: Some(TimestampDataRelation::Sync),
+        diverged: false,
+    };
+    assert_eq!(timestamp.nanos(&timing), Some(1_010_000));
+    assert_eq!(
+        timestamp.duration(&timing),
+        Some(Duration::from_micros(1010))
+    );
+    assert_eq!(timestamp.error_bound(&timing), Duration::ZERO);
+
+    // Bounded by one wrap of the 28-bit counter, prescaled
+    timestamp.diverged = true;
+    assert_eq!(
+        timestamp.error_bound(&timing),
+        Duration::from_nanos(22_369_621_250)
+    );
+
+    let set = TimestampedTracePackets {
+        timestamp: Timestamp {
+            base: None,
+            delta: Some(48),
+            data_relation: None,
+            diverged: false,
+        },
+        earliest: None,
+        packets: vec![],
+        malformed_packets: vec![],
+        raw: vec![],
+        clock_changed: false,
+        incomplete: false,
+        packets_consumed: 1,
+        location: span(0, 1),
+    };
+    assert_eq!(set.nanos(&timing), Some(1000));
+    assert_eq!(set.duration(&timing), Some(Duration::from_micros(1)));
+    assert_eq!(
+        set.interval(&timing),
+        Some((Duration::from_micros(1), Duration::from_micros(1)))
+    );
+
+    let set = TimestampedTracePackets {
+        timestamp: Timestamp {
+            base: None,
+            delta: Some(96),
+            data_relation: Some(TimestampDataRelation::UnknownDelay),
+            diverged: false,
+        },
+        earliest: Some(set.timestamp),
+        ..set
+    };
+    assert_eq!(
+        set.interval(&timing),
+        Some((Duration::from_micros(1), Duration::from_micros(2)))
+    );
+    assert_eq!(Timestamp::default().nanos(&timing), None);
+}
+
+#[test]
+fn clock_change() {
+    use core::num::NonZeroU32;
+    use core::time::Duration;
+
+    let timing = |trace_clock_hz, gts_clock_hz| TimingConfig {
+        trace_clock_hz: NonZeroU32::new(trace_clock_hz).unwrap(),
+        lts_prescaler: LocalTimestampPrescaler::Div1,
+        gts_clock_hz: NonZeroU32::new(gts_clock_hz).unwrap(),
+    };
+    let lts1 = |ts| TracePacket::LocalTimestamp1 {
+        ts,
+        data_relation: TimestampDataRelation::Sync,
+    };
+
+    let mut encoder = Encoder::new(EncoderOptions::default());
+    let mut decoder = Decoder::new(DecoderOptions::default());
+    for packet in [
+        TracePacket::GlobalTimestamp1 {
+            ts: 1000,
+            wrap: true,
+            clkch: false,
+        },
+        TracePacket::GlobalTimestamp2 { ts: 0 },
+        lts1(48),
+        // The lower bits of the first timestamp of the new segment;
+        // GTS2 follows
+        TracePacket::GlobalTimestamp1 {
+            ts: 2000,
+            wrap: false,
+            clkch: true,
+        },
+        TracePacket::PCSample { pc: None },
+        lts1(10),
+        TracePacket::GlobalTimestamp2 { ts: 0 },
+        lts1(20),
+        lts1(20),
+    ] {
+        decoder.push(&encoder.encode(&packet).unwrap()).unwrap();
+    }
+    let sets: Vec<_> = core::iter::from_fn(|| decoder.pull_with_timestamp()).collect();
+
+    assert_eq!(
+        sets.iter()
+            .map(|set| (set.timestamp.base, set.timestamp.delta, set.clock_changed))
+            .collect::<Vec<_>>(),
+        [
+            (Some(1000), Some(48), false),
+            (None, Some(10), true),
+            (Some(2000), Some(20), false),
+            (Some(2000), Some(40), false),
+        ]
+    );
+    assert_eq!(
+        sets[1].to_string(),
+        "[+10] Clock change; PC sample, sleeping"
+    );
+
+    // Each segment is converted with its own clocks, from where the
+    // last one left off. Time starts at the first timestamp.
+    let mut clock = WallClock::new(timing(1_000_000, 1_000_000));
+    clock.add_segment(timing(2_000_000, 500_000));
+    assert_eq!(
+        sets.iter()
+            .map(|set| clock.convert(set))
+            .collect::<Vec<_>>(),
+        [
+            Some(Duration::from_micros(48)),
+            Some(Duration::from_micros(53)),
+            Some(Duration::from_micros(63)),
+            Some(Duration::from_micros(73)),
+        ]
+    );
+    assert_eq!(clock.segment(), 1);
+    assert_eq!(clock.timing(), &timing(2_000_000, 500_000));
+
+    // Timestamps before the first global timestamp share its origin
+    let mut clock = WallClock::new(timing(1_000_000, 1_000_000));
+    let set = |base, delta| TimestampedTracePackets {
+        timestamp: Timestamp {
+            base,
+            delta: Some(delta),
+            data_relation: Some(TimestampDataRelation::Sync),
+            diverged: false,
+        },
+        earliest: None,
+        packets: vec![],
+        malformed_packets: vec![],
+        raw: vec![],
+        clock_changed: false,
+        incomplete: false,
+        packets_consumed: 1,
+        location: span(0, 1),
+    };
+    assert_eq!(
+        [set(None, 10), set(Some(5000), 5), set(Some(5010), 0)]
+            .iter()
+            .map(|set| clock.convert(set))
+            .collect::<Vec<_>>(),
+        [
+            Some(Duration::from_micros(10)),
+            Some(Duration::from_micros(15)),
+            Some(Duration::from_micros(20)),
+        ]
+    );
+
+    // A delayed local timestamp after a clock change is not related to
+    // timestamps of the previous segment
+    let mut decoder = Decoder::new(DecoderOptions::default());
+    for packet in [
+        TracePacket::GlobalTimestamp1 {
+            ts: 1000,
+            wrap: true,
+            clkch: false,
+        },
+        TracePacket::GlobalTimestamp2 { ts: 1 },
+        lts1(48),
+        TracePacket::GlobalTimestamp1 {
+            ts: 2000,
+            wrap: false,
+            clkch: true,
+        },
+        TracePacket::LocalTimestamp1 {
+            ts: 10,
+            data_relation: TimestampDataRelation::UnknownDelay,
+        },
+    ] {
+        decoder.push(&encoder.encode(&packet).unwrap()).unwrap();
+    }
+    assert!(decoder.pull_with_timestamp().is_some());
+    let set = decoder.pull_with_timestamp().unwrap();
+    assert!(set.clock_changed);
+    assert_eq!(set.earliest, Some(Timestamp::default()));
+    let (earliest, latest) = set.interval(&timing(1_000_000, 1_000_000)).unwrap();
+    assert!(earliest <= latest);
+}
+
+#[test]
+fn timeline() {
+    use core::num::NonZeroU32;
+
+    let gts1 = |ts, wrap| TracePacket::GlobalTimestamp1 {
+        ts,
+        wrap,
+        clkch: false,
+    };
+    let lts2 = |ts| TracePacket::LocalTimestamp2 { ts };
+    let group = |ticks, packets, discontinuities| TimelineGroup {
+        ticks,
+        data_relation: TimestampDataRelation::Sync,
+        packets,
+        discontinuities,
+    };
+
+    let mut timeline = Timeline::new(None);
+    let groups: Vec<_> = [
+        // Lower bits before the upper bits are known
+        gts1(0x100, false),
+        TracePacket::GlobalTimestamp2 { ts: 1 },
+        TracePacket::PCSample { pc: None },
+        lts2(6),
+        // Update of the lower bits only
+        gts1(0x200, false),
+        TracePacket::Overflow,
+        lts2(3),
+        // Upper bits changed
+        gts1(0x10, true),
+        lts2(2),
+        TracePacket::GlobalTimestamp2 { ts: 2 },
+        lts2(1),
+        // A global timestamp behind the timeline
+        gts1(0, false),
+        lts2(1),
+    ]
+    .iter()
+    .filter_map(|packet| timeline.push(packet.clone()))
+    .collect();
+
+    assert_eq!(
+        groups,
+        [
+            group(
+                (1 << 26) + 0x106,
+                vec![TracePacket::PCSample { pc: None }],
+                vec![Discontinuity::Reanchor {
+                    from: 0,
+                    to: (1 << 26) + 0x100
+                }]
+            ),
+            group(
+                (1 << 26) + 0x203,
+                vec![TracePacket::Overflow],
+                vec![
+                    Discontinuity::Reanchor {
+                        from: (1 << 26) + 0x106,
+                        to: (1 << 26) + 0x200
+                    },
+                    Discontinuity::Overflow
+                ]
+            ),
+            group((1 << 26) + 0x205, vec![], vec![]),
+            group(
+                (2 << 26) + 0x11,
+                vec![],
+                vec![Discontinuity::Reanchor {
+                    from: (1 << 26) + 0x205,
+                    to: (2 << 26) + 0x10
+                }]
+            ),
+            group(
+                (2 << 26) + 0x12,
+                vec![],
+                vec![Discontinuity::Reanchor {
+                    from: (2 << 26) + 0x11,
+                    to: 2 << 26
+                }]
+            ),
+        ]
+    );
+    assert_eq!(timeline.ticks(), (2 << 26) + 0x12);
+
+    // Global timestamps are scaled to the trace clock, local timestamps
+    // by the prescaler
+    let mut timeline = Timeline::new(Some(TimingConfig {
+        trace_clock_hz: NonZeroU32::new(48_000_000).unwrap(),
+        lts_prescaler: LocalTimestampPrescaler::Div4,
+        gts_clock_hz: NonZeroU32::new(1_000_000).unwrap(),
+    }));
+    assert_eq!(timeline.push(gts1(0x100, false)), None);
+    assert_eq!(timeline.push(TracePacket::GlobalTimestamp2 { ts: 0 }), None);
+    assert_eq!(timeline.push(lts2(3)).unwrap().ticks, 0x100 * 48 + 3 * 4);
+
+    // A clock change is reported before the timeline is re-anchored
+    let clkch = TracePacket::GlobalTimestamp1 {
+        ts: 0x200,
+        wrap: false,
+        clkch: true,
+    };
+    assert_eq!(timeline.push(clkch), None);
+    assert_eq!(timeline.push(TracePacket::GlobalTimestamp2 { ts: 0 }), None);
+    assert_eq!(
+        timeline.push(lts2(1)).unwrap().discontinuities,
+        [
+            Discontinuity::ClockChange,
+            Discontinuity::Reanchor {
+                from: 0x100 * 48 + 3 * 4,
+                to: 0x200 * 48
+            }
+        ]
+    );
+}
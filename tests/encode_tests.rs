@@ -0,0 +1,255 @@
+use itm_decode::*;
+
+fn roundtrip(encoder: &mut Encoder, packets: &[TracePacket]) {
+    let mut trace_data = vec![];
+    for packet in packets.iter() {
+        encoder.encode_into(packet, &mut trace_data).unwrap();
+    }
+
+    let mut decoder = Decoder::new(DecoderOptions::default());
+    decoder.push(&trace_data).unwrap();
+    for packet in packets.iter() {
+        assert_eq!(decoder.pull(), Ok(Some(packet.clone())));
+    }
+    assert_eq!(decoder.pull(), Ok(None));
+}
+
+#[test]
+fn encode_instrumentation_packet() {
+    let mut encoder = Encoder::new(EncoderOptions::default());
+    assert_eq!(
+        encoder.encode(&TracePacket::Instrumentation {
+            port: 0b1_0001,
+            payload: Payload::from_slice(&[0b0000_0011, 0b0000_1111, 0b0011_1111, 0b1111_1111])
+                .unwrap(),
+        }),
+        Ok([
+            0b1000_1011,
+            0b0000_0011,
+            0b0000_1111,
+            0b0011_1111,
+            0b1111_1111,
+        ]
+        .to_vec())
+    );
+}
+
+#[test]
+fn encode_global_timestamp_packets() {
+    let mut encoder = Encoder::new(EncoderOptions::default());
+    #[rustfmt::skip]
+    assert_eq!(
+        encoder.encode(&TracePacket::GlobalTimestamp1 {
+            ts: 0b00000_0000100_0100000_0000000,
+            wrap: true,
+            clkch: true,
+        }),
+        Ok([
+            0b1001_0100,
+            0b1000_0000,
+            0b1010_0000,
+            0b1000_0100,
+            0b0110_0000,
+        ].to_vec())
+    );
+
+    let mut encoder = Encoder::new(EncoderOptions {
+        gts2_width: GlobalTimestampWidth::Bits64,
+        ..EncoderOptions::default()
+    });
+    #[rustfmt::skip]
+    assert_eq!(
+        encoder.encode(&TracePacket::GlobalTimestamp2 {
+            ts: 0b111_1110100_0000001_0010001_1110100_0111101,
+        }),
+        Ok([
+            0b1011_0100,
+            0b1011_1101,
+            0b1111_0100,
+            0b1001_0001,
+            0b1000_0001,
+            0b1111_0100,
+            0b0000_0111,
+        ].to_vec())
+    );
+}
+
+#[test]
+fn roundtrip_all_packets() {
+    roundtrip(
+        &mut Encoder::new(EncoderOptions::default()),
+        &[
+            TracePacket::Sync,
+            TracePacket::Overflow,
+            TracePacket::LocalTimestamp1 {
+                ts: 0,
+                data_relation: TimestampDataRelation::UnknownDelay,
+            },
+            TracePacket::LocalTimestamp1 {
+                ts: (1 << 28) - 1,
+                data_relation: TimestampDataRelation::AssocEventDelay,
+            },
+            TracePacket::LocalTimestamp2 { ts: 6 },
+            TracePacket::GlobalTimestamp1 {
+                ts: 0b10_1010,
+                wrap: false,
+                clkch: true,
+            },
+            TracePacket::GlobalTimestamp2 { ts: (1 << 22) - 1 },
+            TracePacket::Extension { page: 0b101 },
+            TracePacket::Instrumentation {
+                port: 0b101 * 32 + 31,
+                payload: Payload::from_slice(b"h").unwrap(),
+            },
+            TracePacket::Extension { page: 0 },
+            TracePacket::Instrumentation {
+                port: 0,
+                payload: Payload::from_slice(b"hi").unwrap(),
+            },
+            TracePacket::EventCounterWrap {
+                cyc: true,
+                fold: false,
+                lsu: true,
+                sleep: false,
+                exc: true,
+                cpi: false,
+            },
+            TracePacket::ExceptionTrace {
+                exception: ExceptionType::SysTick,
+                action: ExceptionAction::Entered,
+            },
+            TracePacket::ExceptionTrace {
+                exception: ExceptionType::ThreadMode,
+                action: ExceptionAction::Returned,
+            },
+            TracePacket::ExceptionTrace {
+                exception: ExceptionType::Interrupt { irqn: 495 },
+                action: ExceptionAction::Exited,
+            },
+            TracePacket::PCSample { pc: None },
+            TracePacket::PCSample {
+                pc: Some(0x0800_0400),
+            },
+            TracePacket::DataTracePC {
+                comparator: 3,
+                pc: 0x0800_0400,
+            },
+            TracePacket::DataTraceAddress {
+                comparator: 1,
+                data: Payload::from_slice(&[0x34, 0x12]).unwrap(),
+            },
+            TracePacket::DataTraceValue {
+                comparator: 2,
+                access_type: MemoryAccessType::Read,
+                access_size: AccessSize::Word,
+                value: Payload::from_slice(&[0x78, 0x56, 0x34, 0x12]).unwrap(),
+            },
+        ],
+    );
+}
+
+#[test]
+fn roundtrip_long_sync() {
+    roundtrip(
+        &mut Encoder::new(EncoderOptions {
+            sync_zeros: 63,
+            ..EncoderOptions::default()
+        }),
+        &[TracePacket::Sync, TracePacket::Overflow],
+    );
+}
+
+#[test]
+fn encode_invalid_packets() {
+    let mut encoder = Encoder::new(EncoderOptions::default());
+    for (packet, err) in [
+        (
+            TracePacket::Instrumentation {
+                port: 0,
+                payload: Payload::from_slice(&[1, 2, 3]).unwrap(),
+            },
+            UnencodablePacket::InvalidPayloadSize(3),
+        ),
+        (
+            TracePacket::Instrumentation {
+                port: 40,
+                payload: Payload::from_slice(&[1]).unwrap(),
+            },
+            UnencodablePacket::InvalidPort(40),
+        ),
+        (
+            TracePacket::DataTraceValue {
+                comparator: 0,
+                access_type: MemoryAccessType::Write,
+                access_size: AccessSize::Halfword,
+                value: Payload::from_slice(&[1]).unwrap(),
+            },
+            UnencodablePacket::InvalidPayloadSize(1),
+        ),
+        (
+            TracePacket::LocalTimestamp1 {
+                ts: 1 << 28,
+                data_relation: TimestampDataRelation::Sync,
+            },
+            UnencodablePacket::TimestampOverflow {
+                ts: 1 << 28,
+                bits: 28,
+            },
+        ),
+        (
+            TracePacket::GlobalTimestamp2 { ts: 1 << 22 },
+            UnencodablePacket::TimestampOverflow {
+                ts: 1 << 22,
+                bits: 22,
+            },
+        ),
+        (
+            TracePacket::LocalTimestamp2 { ts: 7 },
+            UnencodablePacket::InvalidLocalTimestamp2(7),
+        ),
+        (
+            TracePacket::ExceptionTrace {
+                exception: ExceptionType::Interrupt { irqn: 496 },
+                action: ExceptionAction::Entered,
+            },
+            UnencodablePacket::InvalidExceptionNumber(512),
+        ),
+        (
+            TracePacket::DataTracePC {
+                comparator: 4,
+                pc: 0,
+            },
+            UnencodablePacket::InvalidComparator(4),
+        ),
+    ]
+    .iter()
+    {
+        let mut buf = vec![0xFF];
+        assert_eq!(encoder.encode_into(packet, &mut buf), Err(err.clone()));
+        assert_eq!(buf, [0xFF]);
+    }
+
+    // Ports above 31 are encoded after an Extension packet of their page
+    let port = |port| TracePacket::Instrumentation {
+        port,
+        payload: Payload::from_slice(&[1]).unwrap(),
+    };
+    assert_eq!(
+        encoder.encode(&TracePacket::Extension { page: 1 }),
+        Ok(vec![0b0001_1000])
+    );
+    assert_eq!(encoder.encode(&port(40)), Ok(vec![0b0100_0001, 1]));
+    assert_eq!(
+        encoder.encode(&port(8)),
+        Err(UnencodablePacket::InvalidPort(8))
+    );
+
+    let mut encoder = Encoder::new(EncoderOptions {
+        sync_zeros: 50,
+        ..EncoderOptions::default()
+    });
+    assert_eq!(
+        encoder.encode(&TracePacket::Sync),
+        Err(UnencodablePacket::InvalidSyncLength(50))
+    );
+}
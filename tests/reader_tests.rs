@@ -0,0 +1,183 @@
+#![cfg(feature = "std")]
+
+use itm_decode::*;
+use std::io::{self, Cursor, Read};
+
+/// A source that yields a single byte per read, interleaved with
+/// interrupted reads.
+struct Trickle<R> {
+    inner: R,
+    interrupt: bool,
+}
+
+impl<R: Read> Read for Trickle<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+
+        let len = buf.len().min(1);
+        self.inner.read(&mut buf[..len])
+    }
+}
+
+/// A source that fails after its data has been read.
+struct Failing<R>(R);
+
+impl<R: Read> Read for Failing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf)? {
+            0 => Err(io::ErrorKind::BrokenPipe.into()),
+            len => Ok(len),
+        }
+    }
+}
+
+#[rustfmt::skip]
+const TRACE_DATA: [u8; 9] = [
+    // Instrumentation packet
+    0b0000_1010,
+    b'h',
+    b'i',
+
+    // Malformed header
+    0b1111_1111,
+
+    // PC sample (sleeping)
+    0b0001_0101,
+    0b0000_0000,
+
+    // LTS1
+    0b1100_0000,
+    0b1100_1001,
+    0b0000_0001,
+];
+
+#[test]
+fn read_packets() {
+    let expected = [
+        Ok(TracePacket::Instrumentation {
+            port: 1,
+            payload: Payload::from_slice(b"hi").unwrap(),
+        }),
+        Err(MalformedPacket::InvalidHardwareDisc {
+            disc_id: 31,
+            size: 3,
+        }),
+        Ok(TracePacket::PCSample { pc: None }),
+        Ok(TracePacket::LocalTimestamp1 {
+            ts: 0b1_1001001,
+            data_relation: TimestampDataRelation::Sync,
+        }),
+    ];
+
+    let reader = ItmReader::new(Cursor::new(TRACE_DATA), DecoderOptions::default());
+    assert_eq!(reader.collect::<io::Result<Vec<_>>>().unwrap(), expected);
+
+    let reader = ItmReader::new(
+        Trickle {
+            inner: Cursor::new(TRACE_DATA),
+            interrupt: false,
+        },
+        DecoderOptions::default(),
+    );
+    assert_eq!(reader.collect::<io::Result<Vec<_>>>().unwrap(), expected);
+}
+
+#[test]
+fn read_heuristic_eof() {
+    // Fewer bytes than the lookahead follow the malformed header
+    let reader = ItmReader::new(
+        Cursor::new(TRACE_DATA),
+        DecoderOptions {
+            recovery: RecoveryPolicy::Heuristic,
+            ..DecoderOptions::default()
+        },
+    );
+    assert_eq!(reader.filter(|p| p.is_ok()).count(), 4);
+}
+
+#[test]
+fn read_timestamped_packets() {
+    let mut reader = TimestampedItmReader::new(Cursor::new(TRACE_DATA), DecoderOptions::default());
+    let set = reader.next().unwrap().unwrap();
+    assert_eq!(set.packets.len(), 2);
+    assert_eq!(set.malformed_packets.len(), 1);
+    assert_eq!(set.timestamp.delta, Some(0b1_1001001));
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn read_timestamped_flush() {
+    // A PC sample not followed by a local timestamp
+    let mut data = TRACE_DATA.to_vec();
+    data.extend_from_slice(&[0b0001_0101, 0b0000_0000]);
+
+    let sets = TimestampedItmReader::new(Cursor::new(data), DecoderOptions::default())
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(sets.len(), 2);
+    assert!(!sets[0].incomplete);
+    assert!(sets[1].incomplete);
+    assert_eq!(sets[1].packets, [TracePacket::PCSample { pc: None }]);
+    assert_eq!(sets[1].timestamp.delta, Some(0b1_1001001));
+}
+
+#[test]
+fn read_timestamped_recovering() {
+    // A malformed header precedes the final packets
+    #[rustfmt::skip]
+    let data = [
+        0b0000_1010, b'h', b'i',
+        0b1111_1111,
+        0b0000_1010, b'c', b'r',
+        0b0001_0101, 0b0000_0000,
+    ];
+
+    let sets = TimestampedItmReader::new(
+        Cursor::new(data),
+        DecoderOptions {
+            recovery: RecoveryPolicy::Heuristic,
+            ..DecoderOptions::default()
+        },
+    )
+    .collect::<io::Result<Vec<_>>>()
+    .unwrap();
+    assert_eq!(sets.len(), 1);
+    assert!(sets[0].incomplete);
+    assert_eq!(sets[0].packets.len(), 3);
+    assert_eq!(sets[0].malformed_packets.len(), 1);
+}
+
+#[test]
+fn read_error() {
+    let mut reader = ItmReader::new(Failing(Cursor::new(TRACE_DATA)), DecoderOptions::default());
+    assert_eq!(reader.by_ref().take(4).filter(|p| p.is_ok()).count(), 4);
+    assert_eq!(
+        reader.next().unwrap().unwrap_err().kind(),
+        io::ErrorKind::BrokenPipe
+    );
+}
+
+#[test]
+fn read_bounded() {
+    let options = || DecoderOptions {
+        capacity: Some(3),
+        ..DecoderOptions::default()
+    };
+    let reader = ItmReader::new(Cursor::new(TRACE_DATA), options());
+    assert_eq!(reader.filter(|p| p.is_ok()).count(), 4);
+
+    // An instrumentation packet payload does not fit in one byte
+    let mut reader = ItmReader::new(
+        Cursor::new(TRACE_DATA),
+        DecoderOptions {
+            capacity: Some(1),
+            ..DecoderOptions::default()
+        },
+    );
+    let err = reader.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    assert_eq!(err.into_inner().unwrap().downcast_ref(), Some(&BufferFull));
+}